@@ -0,0 +1,145 @@
+//! Emit composed text with its marks as standalone combining chars, for
+//! editors and font pipelines that position the marks themselves.
+//!
+//! Tones and diacritics are picked separately, so only the tone can be
+//! split off while â, ơ, ă stay precomposed (ấ -> â U+0301). đ has no
+//! combining form and is always kept as is.
+
+use crate::engine::{compose_char, decompose, Diacritic, Tone};
+
+/// How a class of marks is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Form {
+    /// Part of the letter (ấ)
+    Precomposed,
+    /// A combining char following the letter (a U+0302 U+0301)
+    Combining,
+}
+
+/// The form of the tones and of the diacritics, both precomposed by default
+///
+/// A diacritic written combining takes the tone along, unicode orders the
+/// marks above by position so a precomposed tone would end up before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Normalization {
+    pub tones: Form,
+    pub diacritics: Form,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            tones: Form::Precomposed,
+            diacritics: Form::Precomposed,
+        }
+    }
+}
+
+impl Normalization {
+    /// Only the tone combining, the hybrid some font pipelines want
+    pub fn tones_combining() -> Self {
+        Self {
+            tones: Form::Combining,
+            diacritics: Form::Precomposed,
+        }
+    }
+
+    /// Every mark combining, the same as unicode NFD
+    pub fn all_combining() -> Self {
+        Self {
+            tones: Form::Combining,
+            diacritics: Form::Combining,
+        }
+    }
+}
+
+fn combining_tone(tone: Tone) -> char {
+    match tone {
+        Tone::Acute => '\u{301}',
+        Tone::Grave => '\u{300}',
+        Tone::HookAbove => '\u{309}',
+        Tone::Tilde => '\u{303}',
+        Tone::Dot => '\u{323}',
+    }
+}
+
+fn combining_diacritic(diacritic: Diacritic) -> Option<char> {
+    match diacritic {
+        Diacritic::Circumflex => Some('\u{302}'),
+        Diacritic::Horn => Some('\u{31B}'),
+        Diacritic::Breve => Some('\u{306}'),
+        Diacritic::CrossedD => None,
+    }
+}
+
+// the canonical combining class, marks are written in its order so the
+// output is the one unicode would give (ệ -> e U+0323 U+0302)
+fn combining_class(mark: char) -> u8 {
+    match mark {
+        '\u{31B}' => 216,
+        '\u{323}' => 220,
+        _ => 230,
+    }
+}
+
+/// Rewrite `text` with its marks in the forms of `normalization`, chars
+/// that aren't vietnamese letters are passed through as is
+pub fn normalize(text: &str, normalization: Normalization) -> String {
+    let mut normalized = String::new();
+    for ch in text.chars() {
+        let (base, diacritic, tone) = decompose(ch);
+        let split_diacritic = diacritic
+            .filter(|_| normalization.diacritics == Form::Combining)
+            .and_then(combining_diacritic);
+        let split_tone = tone.filter(|_| {
+            normalization.tones == Form::Combining || split_diacritic.is_some()
+        });
+        let letter = match split_diacritic {
+            Some(_) => base,
+            None => compose_char(base, None, diacritic).unwrap_or(ch),
+        };
+        let letter = match (tone, split_tone) {
+            (Some(tone), None) => compose_char(letter, Some(tone), None).unwrap_or(ch),
+            _ => letter,
+        };
+        let mut marks = split_diacritic
+            .into_iter()
+            .chain(split_tone.map(combining_tone))
+            .collect::<Vec<char>>();
+        marks.sort_by_key(|&mark| combining_class(mark));
+        normalized.push(letter);
+        normalized.extend(marks);
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::to_codepoints;
+
+    #[test]
+    fn only_the_tone_splits_off_with_tones_combining() {
+        let normalized = normalize("ấ", Normalization::tones_combining());
+        assert_eq!(to_codepoints(&normalized), [0xe2, 0x301]);
+        let normalized = normalize("Tiếng Việt", Normalization::tones_combining());
+        assert_eq!(normalized, "Tiê\u{301}ng Viê\u{323}t");
+    }
+
+    #[test]
+    fn all_combining_writes_the_marks_in_unicode_order() {
+        let normalized = normalize("ấ", Normalization::all_combining());
+        assert_eq!(to_codepoints(&normalized), [0x61, 0x302, 0x301]);
+        let normalized = normalize("ệ", Normalization::all_combining());
+        assert_eq!(to_codepoints(&normalized), [0x65, 0x323, 0x302]);
+        let normalized = normalize("ợ", Normalization::all_combining());
+        assert_eq!(to_codepoints(&normalized), [0x6f, 0x31b, 0x323]);
+    }
+
+    #[test]
+    fn precomposed_and_unmarked_chars_stay_as_they_are() {
+        assert_eq!(normalize("tiếng Việt", Normalization::default()), "tiếng Việt");
+        assert_eq!(normalize("đi", Normalization::all_combining()), "đi");
+        assert_eq!(normalize("ab 1-", Normalization::all_combining()), "ab 1-");
+    }
+}
@@ -0,0 +1,396 @@
+//! Convert composed text to the legacy 8 bit vietnamese encodings that
+//! older applications and fonts still expect.
+//!
+//! ASCII is passed through as is, characters neither encoding can represent
+//! become `?`.
+
+const VISCII: [(char, u8); 134] = [
+    ('ạ', 0xd5),
+    ('ă', 0xe5),
+    ('ặ', 0xa3),
+    ('â', 0xe2),
+    ('ậ', 0xa7),
+    ('ẹ', 0xa9),
+    ('ê', 0xea),
+    ('ệ', 0xae),
+    ('ọ', 0xf7),
+    ('ô', 0xf4),
+    ('ộ', 0xb5),
+    ('ơ', 0xbd),
+    ('ợ', 0xfe),
+    ('ị', 0xb8),
+    ('ụ', 0xf8),
+    ('ư', 0xdf),
+    ('ự', 0xf1),
+    ('ỵ', 0xdc),
+    ('Ạ', 0x80),
+    ('Ă', 0xc5),
+    ('Ặ', 0x83),
+    ('Â', 0xc2),
+    ('Ậ', 0x87),
+    ('Ẹ', 0x89),
+    ('Ê', 0xca),
+    ('Ệ', 0x8e),
+    ('Ọ', 0x9a),
+    ('Ô', 0xd4),
+    ('Ộ', 0x93),
+    ('Ơ', 0xb4),
+    ('Ợ', 0x94),
+    ('Ị', 0x98),
+    ('Ụ', 0x9e),
+    ('Ư', 0xbf),
+    ('Ự', 0xb9),
+    ('Ỵ', 0x1e),
+    ('ã', 0xe3),
+    ('ẵ', 0xc7),
+    ('ẫ', 0xe7),
+    ('ẽ', 0xa8),
+    ('ễ', 0xad),
+    ('õ', 0xf5),
+    ('ỗ', 0xb2),
+    ('ỡ', 0xde),
+    ('ĩ', 0xee),
+    ('ũ', 0xfb),
+    ('ữ', 0xe6),
+    ('ỹ', 0xdb),
+    ('Ã', 0xc3),
+    ('Ẵ', 0x05),
+    ('Ẫ', 0x06),
+    ('Ẽ', 0x88),
+    ('Ễ', 0x8d),
+    ('Õ', 0xa0),
+    ('Ỗ', 0x92),
+    ('Ỡ', 0xb3),
+    ('Ĩ', 0xce),
+    ('Ũ', 0x9d),
+    ('Ữ', 0xff),
+    ('Ỹ', 0x19),
+    ('ả', 0xe4),
+    ('ẳ', 0xc6),
+    ('ẩ', 0xa6),
+    ('ẻ', 0xeb),
+    ('ể', 0xac),
+    ('ỏ', 0xf6),
+    ('ổ', 0xb1),
+    ('ở', 0xb7),
+    ('ỉ', 0xef),
+    ('ủ', 0xfc),
+    ('ử', 0xd8),
+    ('ỷ', 0xd6),
+    ('Ả', 0xc4),
+    ('Ẳ', 0x02),
+    ('Ẩ', 0x86),
+    ('Ẻ', 0xcb),
+    ('Ể', 0x8c),
+    ('Ỏ', 0x99),
+    ('Ổ', 0x91),
+    ('Ở', 0x97),
+    ('Ỉ', 0x9b),
+    ('Ủ', 0x9c),
+    ('Ử', 0xbc),
+    ('Ỷ', 0x14),
+    ('à', 0xe0),
+    ('ằ', 0xa2),
+    ('ầ', 0xa5),
+    ('è', 0xe8),
+    ('ề', 0xab),
+    ('ò', 0xf2),
+    ('ồ', 0xb0),
+    ('ờ', 0xb6),
+    ('ì', 0xec),
+    ('ù', 0xf9),
+    ('ừ', 0xd7),
+    ('ỳ', 0xcf),
+    ('À', 0xc0),
+    ('Ằ', 0x82),
+    ('Ầ', 0x85),
+    ('È', 0xc8),
+    ('Ề', 0x8b),
+    ('Ò', 0xd2),
+    ('Ồ', 0x90),
+    ('Ờ', 0x96),
+    ('Ì', 0xcc),
+    ('Ù', 0xd9),
+    ('Ừ', 0xbb),
+    ('Ỳ', 0x9f),
+    ('á', 0xe1),
+    ('ắ', 0xa1),
+    ('ấ', 0xa4),
+    ('é', 0xe9),
+    ('ế', 0xaa),
+    ('ó', 0xf3),
+    ('ố', 0xaf),
+    ('ớ', 0xbe),
+    ('í', 0xed),
+    ('ú', 0xfa),
+    ('ứ', 0xd1),
+    ('ý', 0xfd),
+    ('Á', 0xc1),
+    ('Ắ', 0x81),
+    ('Ấ', 0x84),
+    ('É', 0xc9),
+    ('Ế', 0x8a),
+    ('Ó', 0xd3),
+    ('Ố', 0x8f),
+    ('Ớ', 0x95),
+    ('Í', 0xcd),
+    ('Ú', 0xda),
+    ('Ứ', 0xba),
+    ('Ý', 0xdd),
+    ('đ', 0xf0),
+    ('Đ', 0xd0),
+];
+
+const TCVN3: [(char, u8); 134] = [
+    ('ạ', 0xb9),
+    ('ă', 0xa8),
+    ('ặ', 0xc6),
+    ('â', 0xa9),
+    ('ậ', 0xcb),
+    ('ẹ', 0xd1),
+    ('ê', 0xaa),
+    ('ệ', 0xd6),
+    ('ọ', 0xe4),
+    ('ô', 0xab),
+    ('ộ', 0xe9),
+    ('ơ', 0xac),
+    ('ợ', 0xee),
+    ('ị', 0xde),
+    ('ụ', 0xf4),
+    ('ư', 0xad),
+    ('ự', 0xf9),
+    ('ỵ', 0xfe),
+    ('Ạ', 0x84),
+    ('Ă', 0xa1),
+    ('Ặ', 0x85),
+    ('Â', 0xa2),
+    ('Ậ', 0x86),
+    ('Ẹ', 0x8b),
+    ('Ê', 0xa3),
+    ('Ệ', 0x8c),
+    ('Ọ', 0x96),
+    ('Ô', 0xa4),
+    ('Ộ', 0x97),
+    ('Ơ', 0xa5),
+    ('Ợ', 0x9c),
+    ('Ị', 0x91),
+    ('Ụ', 0x02),
+    ('Ư', 0xa6),
+    ('Ự', 0x12),
+    ('Ỵ', 0x17),
+    ('ã', 0xb7),
+    ('ẵ', 0xbd),
+    ('ẫ', 0xc9),
+    ('ẽ', 0xcf),
+    ('ễ', 0xd4),
+    ('õ', 0xe2),
+    ('ỗ', 0xe7),
+    ('ỡ', 0xec),
+    ('ĩ', 0xdc),
+    ('ũ', 0xf2),
+    ('ữ', 0xf7),
+    ('ỹ', 0xfc),
+    ('Ã', 0x82),
+    ('Ẵ', 0xbf),
+    ('Ẫ', 0xc3),
+    ('Ẽ', 0x89),
+    ('Ễ', 0xd9),
+    ('Õ', 0x94),
+    ('Ỗ', 0xf0),
+    ('Ỡ', 0x9a),
+    ('Ĩ', 0x8f),
+    ('Ũ', 0x9f),
+    ('Ữ', 0x06),
+    ('Ỹ', 0x15),
+    ('ả', 0xb6),
+    ('ẳ', 0xbc),
+    ('ẩ', 0xc8),
+    ('ẻ', 0xce),
+    ('ể', 0xd3),
+    ('ỏ', 0xe1),
+    ('ổ', 0xe6),
+    ('ở', 0xeb),
+    ('ỉ', 0xd8),
+    ('ủ', 0xf1),
+    ('ử', 0xf6),
+    ('ỷ', 0xfb),
+    ('Ả', 0x81),
+    ('Ẳ', 0xba),
+    ('Ẩ', 0xc2),
+    ('Ẻ', 0x88),
+    ('Ể', 0xcd),
+    ('Ỏ', 0x93),
+    ('Ổ', 0xe0),
+    ('Ở', 0x99),
+    ('Ỉ', 0x8e),
+    ('Ủ', 0x9e),
+    ('Ử', 0x05),
+    ('Ỷ', 0x14),
+    ('à', 0xb5),
+    ('ằ', 0xbb),
+    ('ầ', 0xc7),
+    ('è', 0xcc),
+    ('ề', 0xd2),
+    ('ò', 0xdf),
+    ('ồ', 0xe5),
+    ('ờ', 0xea),
+    ('ì', 0xd7),
+    ('ù', 0xef),
+    ('ừ', 0xf5),
+    ('ỳ', 0xfa),
+    ('À', 0x80),
+    ('Ằ', 0xaf),
+    ('Ầ', 0xc1),
+    ('È', 0x87),
+    ('Ề', 0xc5),
+    ('Ò', 0x92),
+    ('Ồ', 0xdb),
+    ('Ờ', 0x98),
+    ('Ì', 0x8d),
+    ('Ù', 0x9d),
+    ('Ừ', 0x04),
+    ('Ỳ', 0x13),
+    ('á', 0xb8),
+    ('ắ', 0xbe),
+    ('ấ', 0xca),
+    ('é', 0xd0),
+    ('ế', 0xd5),
+    ('ó', 0xe3),
+    ('ố', 0xe8),
+    ('ớ', 0xed),
+    ('í', 0xdd),
+    ('ú', 0xf3),
+    ('ứ', 0xf8),
+    ('ý', 0xfd),
+    ('Á', 0x83),
+    ('Ắ', 0xc0),
+    ('Ấ', 0xc4),
+    ('É', 0x8a),
+    ('Ế', 0xda),
+    ('Ó', 0x95),
+    ('Ố', 0xff),
+    ('Ớ', 0x9b),
+    ('Í', 0x90),
+    ('Ú', 0x01),
+    ('Ứ', 0x11),
+    ('Ý', 0x16),
+    ('đ', 0xae),
+    ('Đ', 0xa7),
+];
+
+fn encode(text: &str, table: &[(char, u8)]) -> Vec<u8> {
+    text.chars()
+        .map(|ch| {
+            if ch.is_ascii() {
+                return ch as u8;
+            }
+            table
+                .iter()
+                .find(|&&(table_ch, _)| table_ch == ch)
+                .map_or(b'?', |&(_, byte)| byte)
+        })
+        .collect()
+}
+
+/// Encode as VISCII (RFC 1456)
+pub fn to_viscii(text: &str) -> Vec<u8> {
+    encode(text, &VISCII)
+}
+
+/// Encode as TCVN3 (ABC)
+///
+/// TCVN3 fonts only have the precomposed lowercase letters, the uppercase
+/// letters with a tone use the TCVN 5712:1993 code points below 0x20 and in
+/// 0x80 - 0x9f, which not every TCVN3 font renders.
+pub fn to_tcvn3(text: &str) -> Vec<u8> {
+    encode(text, &TCVN3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every letter with a tone or diacritic, in the order of the tables
+    // below, which come from iconv
+    const LOWERCASE: &str = "áàảãạăắằẳẵặâấầẩẫậéèẻẽẹêếềểễệíìỉĩịóòỏõọôốồổỗộơớờởỡợúùủũụưứừửữựýỳỷỹỵđ";
+    const UPPERCASE: &str = "ÁÀẢÃẠĂẮẰẲẴẶÂẤẦẨẪẬÉÈẺẼẸÊẾỀỂỄỆÍÌỈĨỊÓÒỎÕỌÔỐỒỔỖỘƠỚỜỞỠỢÚÙỦŨỤƯỨỪỬỮỰÝỲỶỸỴĐ";
+
+    const VISCII_LOWERCASE: [u8; 67] = [
+        0xe1, 0xe0, 0xe4, 0xe3, 0xd5, 0xe5, 0xa1, 0xa2, 0xc6, 0xc7,
+        0xa3, 0xe2, 0xa4, 0xa5, 0xa6, 0xe7, 0xa7, 0xe9, 0xe8, 0xeb,
+        0xa8, 0xa9, 0xea, 0xaa, 0xab, 0xac, 0xad, 0xae, 0xed, 0xec,
+        0xef, 0xee, 0xb8, 0xf3, 0xf2, 0xf6, 0xf5, 0xf7, 0xf4, 0xaf,
+        0xb0, 0xb1, 0xb2, 0xb5, 0xbd, 0xbe, 0xb6, 0xb7, 0xde, 0xfe,
+        0xfa, 0xf9, 0xfc, 0xfb, 0xf8, 0xdf, 0xd1, 0xd7, 0xd8, 0xe6,
+        0xf1, 0xfd, 0xcf, 0xd6, 0xdb, 0xdc, 0xf0,
+    ];
+    const VISCII_UPPERCASE: [u8; 67] = [
+        0xc1, 0xc0, 0xc4, 0xc3, 0x80, 0xc5, 0x81, 0x82, 0x02, 0x05,
+        0x83, 0xc2, 0x84, 0x85, 0x86, 0x06, 0x87, 0xc9, 0xc8, 0xcb,
+        0x88, 0x89, 0xca, 0x8a, 0x8b, 0x8c, 0x8d, 0x8e, 0xcd, 0xcc,
+        0x9b, 0xce, 0x98, 0xd3, 0xd2, 0x99, 0xa0, 0x9a, 0xd4, 0x8f,
+        0x90, 0x91, 0x92, 0x93, 0xb4, 0x95, 0x96, 0x97, 0xb3, 0x94,
+        0xda, 0xd9, 0x9c, 0x9d, 0x9e, 0xbf, 0xba, 0xbb, 0xbc, 0xff,
+        0xb9, 0xdd, 0x9f, 0x14, 0x19, 0x1e, 0xd0,
+    ];
+    const TCVN3_LOWERCASE: [u8; 67] = [
+        0xb8, 0xb5, 0xb6, 0xb7, 0xb9, 0xa8, 0xbe, 0xbb, 0xbc, 0xbd,
+        0xc6, 0xa9, 0xca, 0xc7, 0xc8, 0xc9, 0xcb, 0xd0, 0xcc, 0xce,
+        0xcf, 0xd1, 0xaa, 0xd5, 0xd2, 0xd3, 0xd4, 0xd6, 0xdd, 0xd7,
+        0xd8, 0xdc, 0xde, 0xe3, 0xdf, 0xe1, 0xe2, 0xe4, 0xab, 0xe8,
+        0xe5, 0xe6, 0xe7, 0xe9, 0xac, 0xed, 0xea, 0xeb, 0xec, 0xee,
+        0xf3, 0xef, 0xf1, 0xf2, 0xf4, 0xad, 0xf8, 0xf5, 0xf6, 0xf7,
+        0xf9, 0xfd, 0xfa, 0xfb, 0xfc, 0xfe, 0xae,
+    ];
+    const TCVN3_UPPERCASE: [u8; 67] = [
+        0x83, 0x80, 0x81, 0x82, 0x84, 0xa1, 0xc0, 0xaf, 0xba, 0xbf,
+        0x85, 0xa2, 0xc4, 0xc1, 0xc2, 0xc3, 0x86, 0x8a, 0x87, 0x88,
+        0x89, 0x8b, 0xa3, 0xda, 0xc5, 0xcd, 0xd9, 0x8c, 0x90, 0x8d,
+        0x8e, 0x8f, 0x91, 0x95, 0x92, 0x93, 0x94, 0x96, 0xa4, 0xff,
+        0xdb, 0xe0, 0xf0, 0x97, 0xa5, 0x9b, 0x98, 0x99, 0x9a, 0x9c,
+        0x01, 0x9d, 0x9e, 0x9f, 0x02, 0xa6, 0x11, 0x04, 0x05, 0x06,
+        0x12, 0x16, 0x13, 0x14, 0x15, 0x17, 0xa7,
+    ];
+
+    #[test]
+    fn viscii_encodes_every_letter() {
+        assert_eq!(to_viscii(LOWERCASE), VISCII_LOWERCASE);
+        assert_eq!(to_viscii(UPPERCASE), VISCII_UPPERCASE);
+    }
+
+    #[test]
+    fn tcvn3_encodes_every_letter() {
+        assert_eq!(to_tcvn3(LOWERCASE), TCVN3_LOWERCASE);
+        assert_eq!(to_tcvn3(UPPERCASE), TCVN3_UPPERCASE);
+    }
+
+    #[test]
+    fn tables_decode_back() {
+        for table in [&VISCII, &TCVN3].iter() {
+            let text = LOWERCASE.chars().chain(UPPERCASE.chars()).collect::<String>();
+            let decoded = encode(&text, *table)
+                .into_iter()
+                .map(|byte| {
+                    table
+                        .iter()
+                        .find(|&&(_, table_byte)| table_byte == byte)
+                        .map(|&(ch, _)| ch)
+                        .unwrap()
+                })
+                .collect::<String>();
+            assert_eq!(decoded, text);
+        }
+    }
+
+    #[test]
+    fn ascii_passes_through() {
+        assert_eq!(to_viscii("Viet Nam 1945!"), b"Viet Nam 1945!");
+        assert_eq!(to_tcvn3("Viet Nam 1945!"), b"Viet Nam 1945!");
+    }
+
+    #[test]
+    fn unknown_chars_become_question_marks() {
+        assert_eq!(to_viscii("ç€"), b"??");
+        assert_eq!(to_tcvn3("ç€"), b"??");
+    }
+}
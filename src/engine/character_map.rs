@@ -1,4 +1,25 @@
-pub const DOT_MAP: [(char, char); 24] = [
+//! Tables putting one tone on each vietnamese vowel
+//!
+//! Every table maps the 24 untoned vowels to the same vowel carrying the
+//! tone, lowercase first then uppercase, each in the order a ă â e ê o ô ơ
+//! i u ư y. Toned or non vowel chars aren't keys, use `lookup` to get
+//! `None` for them.
+//!
+//! Every vowel with a diacritic takes every tone, the hook above and the
+//! tilde included (ể ỡ ử ẳ ẵ).
+
+/// Pairs of (untoned vowel, toned vowel)
+pub type CharacterMap = [(char, char); 24];
+
+/// The toned form of `ch` in `map`, `None` if `ch` isn't an untoned vowel
+pub fn lookup(map: &CharacterMap, ch: char) -> Option<char> {
+    map.iter()
+        .find(|&&(base_ch, _)| base_ch == ch)
+        .map(|&(_, toned_ch)| toned_ch)
+}
+
+/// Dot below (nặng), ạ
+pub const DOT_MAP: CharacterMap = [
     ('a', 'ạ'),
     ('ă', 'ặ'),
     ('â', 'ậ'),
@@ -25,7 +46,8 @@ pub const DOT_MAP: [(char, char); 24] = [
     ('Y', 'Ỵ'),
 ];
 
-pub const TILDE_MAP: [(char, char); 24] = [
+/// Tilde (ngã), ã
+pub const TILDE_MAP: CharacterMap = [
     ('a', 'ã'),
     ('ă', 'ẵ'),
     ('â', 'ẫ'),
@@ -52,7 +74,8 @@ pub const TILDE_MAP: [(char, char); 24] = [
     ('Y', 'Ỹ'),
 ];
 
-pub const HOOK_ABOVE_MAP: [(char, char); 24] = [
+/// Hook above (hỏi), ả
+pub const HOOK_ABOVE_MAP: CharacterMap = [
     ('a', 'ả'),
     ('ă', 'ẳ'),
     ('â', 'ẩ'),
@@ -79,7 +102,8 @@ pub const HOOK_ABOVE_MAP: [(char, char); 24] = [
     ('Y', 'Ỷ'),
 ];
 
-pub const GRAVE_MAP: [(char, char); 24] = [
+/// Grave (huyền), à
+pub const GRAVE_MAP: CharacterMap = [
     ('a', 'à'),
     ('ă', 'ằ'),
     ('â', 'ầ'),
@@ -106,7 +130,8 @@ pub const GRAVE_MAP: [(char, char); 24] = [
     ('Y', 'Ỳ'),
 ];
 
-pub const ACUTE_MAP: [(char, char); 24] = [
+/// Acute (sắc), á
+pub const ACUTE_MAP: CharacterMap = [
     ('a', 'á'),
     ('ă', 'ắ'),
     ('â', 'ấ'),
@@ -38,6 +38,22 @@ pub mod keycodes {
     pub const KEY_8: u32 = 17;
     pub const KEY_9: u32 = 18;
     pub const KEY_0: u32 = 19;
+    // keypad digits, with num lock on
+    pub const KEY_KP_1: u32 = 87;
+    pub const KEY_KP_2: u32 = 88;
+    pub const KEY_KP_3: u32 = 89;
+    pub const KEY_KP_4: u32 = 83;
+    pub const KEY_KP_5: u32 = 84;
+    pub const KEY_KP_6: u32 = 85;
+    pub const KEY_KP_7: u32 = 79;
+    pub const KEY_KP_8: u32 = 80;
+    pub const KEY_KP_9: u32 = 81;
+    pub const KEY_KP_0: u32 = 90;
+    // punctuation
+    pub const KEY_MINUS: u32 = 20;
+    pub const KEY_APOSTROPHE: u32 = 48;
+    pub const KEY_GRAVE: u32 = 49;
+    pub const KEY_BACKSLASH: u32 = 51;
     // other
     pub const KEY_SPACE: u32 = 65;
     pub const KEY_TAB: u32 = 23;
@@ -1,110 +1,378 @@
 mod key_codes;
 mod vni;
 mod util;
-mod character_map;
+pub mod character_map;
 
 pub use key_codes::keycodes;
-use vni::Vni;
+pub use character_map::CharacterMap;
+pub use util::{decompose, compose_char, variants_of, to_codepoints};
+pub use vni::{
+    Vni, VniBuilder, VniConfig, VniSnapshot, ToneStyle, Mark, Transformation, Observer, OverflowPolicy,
+    ConfigError, EnterBehavior, OutputMode, compose_from_triggers,
+};
 
 pub struct Engine {
     vni: Vni
 }
 
-#[derive(Debug, Clone)]
+/// A typing method turning key presses into the actions fixing up the
+/// document, so a host can switch methods at runtime
+pub trait InputEngine {
+    /// See `Vni::handle_key` for what the actions mean
+    #[must_use = "apply the returned actions to the document"]
+    fn handle_key(&mut self, key: PhysicKey) -> Vec<Action>;
+}
+
+impl InputEngine for Vni {
+    fn handle_key(&mut self, key: PhysicKey) -> Vec<Action> {
+        Vni::handle_key(self, key)
+    }
+}
+
+const ENGINE_NAMES: [&str; 1] = ["vni"];
+
+/// Names `engine_by_name` knows, for a settings dropdown
+///
+/// Only `vni` for now. There's no Telex or VIQR engine yet, `telex` and
+/// `viqr` aren't listed and `engine_by_name` gives `None` for them until
+/// they exist.
+pub fn available_engines() -> &'static [&'static str] {
+    &ENGINE_NAMES
+}
+
+/// The engine called `name` in `available_engines` with its default
+/// config, `None` for any other name
+pub fn engine_by_name(name: &str) -> Option<Box<dyn InputEngine>> {
+    match name {
+        "vni" => Some(Box::new(Vni::new())),
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PhysicKey {
     pub keycode: u32,
     pub state: KeyState,
     pub cap: Option<KeyCap>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum KeyState {
     KeyPress,
     KeyRelease
 }
 
-#[derive(Debug, Clone)]
+/// Modifier that changes the char a key types
+///
+/// Shift uppercases letters and turns the digit row into its symbols, so
+/// shift + 6 types `^` and is never the circumflex trigger. Caps lock only
+/// uppercases letters, digits stay digits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum KeyCap {
     Shift,
     CapsLock
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Action {
     Insert(char),
-    Backspace(usize)
-}
-
-impl Into<char> for PhysicKey {
-    fn into(self) -> char {
-        match self.keycode {
-            keycodes::KEY_A => 'a',
-            keycodes::KEY_B => 'b',
-            keycodes::KEY_C => 'c',
-            keycodes::KEY_D => 'd',
-            keycodes::KEY_E => 'e',
-            keycodes::KEY_F => 'f',
-            keycodes::KEY_G => 'g',
-            keycodes::KEY_H => 'h',
-            keycodes::KEY_I => 'i',
-            keycodes::KEY_J => 'j',
-            keycodes::KEY_K => 'k',
-            keycodes::KEY_L => 'l',
-            keycodes::KEY_M => 'm',
-            keycodes::KEY_N => 'n',
-            keycodes::KEY_O => 'o',
-            keycodes::KEY_P => 'p',
-            keycodes::KEY_Q => 'q',
-            keycodes::KEY_R => 'r',
-            keycodes::KEY_S => 's',
-            keycodes::KEY_T => 't',
-            keycodes::KEY_U => 'u',
-            keycodes::KEY_V => 'v',
-            keycodes::KEY_W => 'w',
-            keycodes::KEY_X => 'x',
-            keycodes::KEY_Y => 'y',
-            keycodes::KEY_Z => 'z',
-            // numbers
-            keycodes::KEY_1 => '1',
-            keycodes::KEY_2 => '2',
-            keycodes::KEY_3 => '3',
-            keycodes::KEY_4 => '4',
-            keycodes::KEY_5 => '5',
-            keycodes::KEY_6 => '6',
-            keycodes::KEY_7 => '7',
-            keycodes::KEY_8 => '8',
-            keycodes::KEY_9 => '9',
-            keycodes::KEY_0 => '0',
-            _ => '\0'
-        }
+    Backspace(usize),
+    /// Replace the given number of chars before the caret with the whole
+    /// syllable as composed, in one go, only given with
+    /// `OutputMode::ReplaceWord`
+    ReplaceWord(usize, String),
+}
+
+/// What a host intercepting keys does with one, see
+/// `Vni::handle_key_response`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyResponse {
+    /// Actions to apply to the document, without the key in it
+    pub actions: Vec<Action>,
+    /// Don't pass the key on to the app, the actions make up for it (a
+    /// trigger that composed). False means the key goes through as usual
+    /// before the actions are applied.
+    pub swallow_original: bool,
+}
+
+/// The tone of a vietnamese vowel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tone {
+    Acute,
+    Grave,
+    HookAbove,
+    Tilde,
+    Dot,
+}
+
+/// The mark changing a letter into another one, â ê ô ă ơ ư and đ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Diacritic {
+    Circumflex,
+    Horn,
+    Breve,
+    CrossedD,
+}
+
+// chars typed by each key without any modifier
+const KEY_CHARS: [(u32, char); 53] = [
+    // alphabet
+    (keycodes::KEY_A, 'a'),
+    (keycodes::KEY_B, 'b'),
+    (keycodes::KEY_C, 'c'),
+    (keycodes::KEY_D, 'd'),
+    (keycodes::KEY_E, 'e'),
+    (keycodes::KEY_F, 'f'),
+    (keycodes::KEY_G, 'g'),
+    (keycodes::KEY_H, 'h'),
+    (keycodes::KEY_I, 'i'),
+    (keycodes::KEY_J, 'j'),
+    (keycodes::KEY_K, 'k'),
+    (keycodes::KEY_L, 'l'),
+    (keycodes::KEY_M, 'm'),
+    (keycodes::KEY_N, 'n'),
+    (keycodes::KEY_O, 'o'),
+    (keycodes::KEY_P, 'p'),
+    (keycodes::KEY_Q, 'q'),
+    (keycodes::KEY_R, 'r'),
+    (keycodes::KEY_S, 's'),
+    (keycodes::KEY_T, 't'),
+    (keycodes::KEY_U, 'u'),
+    (keycodes::KEY_V, 'v'),
+    (keycodes::KEY_W, 'w'),
+    (keycodes::KEY_X, 'x'),
+    (keycodes::KEY_Y, 'y'),
+    (keycodes::KEY_Z, 'z'),
+    // numbers
+    (keycodes::KEY_1, '1'),
+    (keycodes::KEY_2, '2'),
+    (keycodes::KEY_3, '3'),
+    (keycodes::KEY_4, '4'),
+    (keycodes::KEY_5, '5'),
+    (keycodes::KEY_6, '6'),
+    (keycodes::KEY_7, '7'),
+    (keycodes::KEY_8, '8'),
+    (keycodes::KEY_9, '9'),
+    (keycodes::KEY_0, '0'),
+    // keypad, after the digit row so `from_char` gives the digit row
+    (keycodes::KEY_KP_1, '1'),
+    (keycodes::KEY_KP_2, '2'),
+    (keycodes::KEY_KP_3, '3'),
+    (keycodes::KEY_KP_4, '4'),
+    (keycodes::KEY_KP_5, '5'),
+    (keycodes::KEY_KP_6, '6'),
+    (keycodes::KEY_KP_7, '7'),
+    (keycodes::KEY_KP_8, '8'),
+    (keycodes::KEY_KP_9, '9'),
+    (keycodes::KEY_KP_0, '0'),
+    // punctuation
+    (keycodes::KEY_MINUS, '-'),
+    (keycodes::KEY_APOSTROPHE, '\''),
+    (keycodes::KEY_GRAVE, '`'),
+    (keycodes::KEY_BACKSLASH, '\\'),
+    // whitespace
+    (keycodes::KEY_SPACE, ' '),
+    (keycodes::KEY_TAB, '\t'),
+    (keycodes::KEY_ENTER, '\n'),
+];
+
+// chars typed by the digit row and punctuation while shift is held
+const SHIFTED_SYMBOLS: [(char, char); 14] = [
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+    ('-', '_'),
+    ('\'', '"'),
+    ('`', '~'),
+    ('\\', '|'),
+];
+
+/// The char a key types without any modifier, `\0` for keys that don't
+/// type anything
+impl From<PhysicKey> for char {
+    fn from(key: PhysicKey) -> char {
+        KEY_CHARS
+            .iter()
+            .find(|&&(keycode, _)| keycode == key.keycode)
+            .map_or('\0', |&(_, ch)| ch)
     }
 }
 
 impl PhysicKey {
-    pub fn is_whitespace(&self) -> bool {
-        match self.keycode {
-            keycodes::KEY_SPACE => true,
-            keycodes::KEY_TAB => true,
-            keycodes::KEY_ENTER => true,
-            _ => false
+    /// A key press
+    pub fn new(keycode: u32, cap: Option<KeyCap>) -> Self {
+        Self {
+            keycode,
+            state: KeyState::KeyPress,
+            cap,
         }
     }
 
-    pub fn is_arrow(&self) -> bool {
-        match self.keycode {
-            keycodes::KEY_LEFT => true,
-            keycodes::KEY_RIGHT => true,
-            keycodes::KEY_UP => true,
-            keycodes::KEY_DOWN => true,
-            _ => false
+    /// A press of the key typing `ch` without any modifier, for input
+    /// sources giving the lowercase char and the modifier separately
+    pub fn from_char(ch: char, cap: Option<KeyCap>) -> Option<Self> {
+        KEY_CHARS
+            .iter()
+            .find(|&&(_, key_ch)| key_ch == ch)
+            .map(|&(keycode, _)| Self::new(keycode, cap))
+    }
+
+    /// A press of the key typing `ch` with the modifier it takes, shift for
+    /// uppercase letters and the symbols of the digit row (A, !)
+    pub fn from_typed_char(ch: char) -> Option<Self> {
+        if ch.is_ascii_uppercase() {
+            return Self::from_char(ch.to_ascii_lowercase(), Some(KeyCap::Shift));
+        }
+        match SHIFTED_SYMBOLS.iter().find(|&&(_, symbol)| symbol == ch) {
+            Some(&(unshifted, _)) => Self::from_char(unshifted, Some(KeyCap::Shift)),
+            None => Self::from_char(ch, None),
+        }
+    }
+
+    /// The char the key types with its modifier applied
+    pub fn to_char(&self) -> char {
+        let ch: char = self.clone().into();
+        match self.cap {
+            Some(_) if ch.is_ascii_alphabetic() => ch.to_ascii_uppercase(),
+            Some(KeyCap::Shift) if !self.is_keypad() => SHIFTED_SYMBOLS
+                .iter()
+                .find(|&&(digit, _)| digit == ch)
+                .map_or(ch, |&(_, symbol)| symbol),
+            _ => ch
         }
     }
 
+    pub fn is_whitespace(&self) -> bool {
+        matches!(
+            self.keycode,
+            keycodes::KEY_SPACE | keycodes::KEY_TAB | keycodes::KEY_ENTER
+        )
+    }
+
+    pub fn is_arrow(&self) -> bool {
+        matches!(
+            self.keycode,
+            keycodes::KEY_LEFT
+                | keycodes::KEY_RIGHT
+                | keycodes::KEY_UP
+                | keycodes::KEY_DOWN
+        )
+    }
+
+    /// Whether the key is a digit of the numeric keypad, which types the
+    /// same digit as the digit row
+    pub fn is_keypad(&self) -> bool {
+        matches!(
+            self.keycode,
+            keycodes::KEY_KP_0
+                | keycodes::KEY_KP_1
+                | keycodes::KEY_KP_2
+                | keycodes::KEY_KP_3
+                | keycodes::KEY_KP_4
+                | keycodes::KEY_KP_5
+                | keycodes::KEY_KP_6
+                | keycodes::KEY_KP_7
+                | keycodes::KEY_KP_8
+                | keycodes::KEY_KP_9
+        )
+    }
+
     pub fn is_backspace(&self) -> bool {
         self.keycode == keycodes::KEY_BACKSPACE
     }
 }
 
+impl Action {
+    /// How many chars the action adds to the document, negative for a
+    /// backspace
+    pub fn char_delta(&self) -> isize {
+        match *self {
+            Action::Insert(_) => 1,
+            Action::Backspace(amount) => -(amount as isize),
+            Action::ReplaceWord(amount, ref word) => {
+                word.chars().count() as isize - amount as isize
+            }
+        }
+    }
+
+    /// How many UTF-8 bytes the action adds to the document, negative for a
+    /// backspace (ế is 3 bytes)
+    ///
+    /// `before` is the text before the caret, only read to know how long
+    /// the chars a backspace deletes are.
+    pub fn byte_delta(&self, before: &str) -> isize {
+        let deleted_len = |amount: usize| -> usize {
+            before
+                .chars()
+                .rev()
+                .take(amount)
+                .map(char::len_utf8)
+                .sum()
+        };
+        match *self {
+            Action::Insert(ch) => ch.len_utf8() as isize,
+            Action::Backspace(amount) => -(deleted_len(amount) as isize),
+            Action::ReplaceWord(amount, ref word) => {
+                word.len() as isize - deleted_len(amount) as isize
+            }
+        }
+    }
+}
+
+/// Apply actions to `text` the way a document would
+///
+/// Works on chars, a backspace deletes one char whatever its UTF-8 length
+/// (ế is one) and backspacing past the start leaves an empty string.
+pub fn apply_actions(text: &str, actions: &[Action]) -> String {
+    let mut chars = text.chars().collect::<Vec<char>>();
+    for action in actions {
+        match *action {
+            Action::Insert(ch) => chars.push(ch),
+            Action::Backspace(amount) => {
+                let len = chars.len();
+                chars.truncate(len.saturating_sub(amount));
+            }
+            Action::ReplaceWord(amount, ref word) => {
+                let len = chars.len();
+                chars.truncate(len.saturating_sub(amount));
+                chars.extend(word.chars());
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// The fewest actions turning `old` into `new`: backspace to the first
+/// char that differs and type the rest
+pub fn diff_actions(old: &str, new: &str) -> Vec<Action> {
+    let old = old.chars().collect::<Vec<char>>();
+    let new = new.chars().collect::<Vec<char>>();
+    let common_len = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(old_ch, new_ch)| old_ch == new_ch)
+        .count();
+    let mut actions = Vec::new();
+    if old.len() > common_len {
+        actions.push(Action::Backspace(old.len() - common_len));
+    }
+    actions.extend(new[common_len..].iter().map(|&ch| Action::Insert(ch)));
+    actions
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Engine {
     pub fn new() -> Self {
         Self {
@@ -112,7 +380,58 @@ impl Engine {
         }
     }
 
+    #[must_use = "apply the returned actions to the document"]
     pub fn handle_key(&mut self, key: PhysicKey) -> Vec<Action> {
         self.vni.handle_key(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_actions_backspaces_whole_chars() {
+        assert_eq!(apply_actions("tiế", &[Action::Backspace(1)]), "ti");
+        assert_eq!(apply_actions("tiếng", &[Action::Backspace(4)]), "t");
+        assert_eq!(apply_actions("ườ", &[Action::Backspace(5)]), "");
+        assert_eq!(apply_actions("tiê", &[Action::Backspace(1), Action::Insert('ế')]), "tiế");
+        assert_eq!(apply_actions("tiế", &[Action::ReplaceWord(3, "tiền".to_string())]), "tiền");
+    }
+
+    #[test]
+    fn byte_delta_counts_utf8_lengths() {
+        assert_eq!(Action::Insert('ế').byte_delta(""), 3);
+        assert_eq!(Action::Insert('e').byte_delta(""), 1);
+        assert_eq!(Action::Backspace(1).byte_delta("tiế"), -3);
+        assert_eq!(Action::Backspace(2).byte_delta("tiế"), -4);
+        assert_eq!(Action::Backspace(9).byte_delta("tiế"), -5);
+        assert_eq!(Action::ReplaceWord(1, "ề".to_string()).byte_delta("tiế"), 0);
+        assert_eq!(Action::Backspace(1).char_delta(), -1);
+    }
+
+    #[test]
+    fn diff_actions_works_on_chars() {
+        assert_eq!(diff_actions("tiê", "tiế"), [Action::Backspace(1), Action::Insert('ế')]);
+        assert_eq!(diff_actions("ườ", "ườ"), []);
+        assert_eq!(apply_actions("tiengs", &diff_actions("tiengs", "tiếng")), "tiếng");
+    }
+
+    #[test]
+    fn every_listed_engine_can_be_built() {
+        for name in available_engines() {
+            assert!(engine_by_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(available_engines(), ["vni"]);
+        assert!(engine_by_name("telex").is_none());
+        assert!(engine_by_name("viqr").is_none());
+        assert!(engine_by_name("").is_none());
+    }
+
+    #[test]
+    fn engines_can_move_to_another_thread() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Engine>();
+        assert_send::<Vni>();
+    }
+}
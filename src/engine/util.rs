@@ -1,71 +1,392 @@
-use regex::Regex;
+use super::{Tone, Diacritic, character_map};
+use super::character_map::CharacterMap;
 
+/// Strip both the tone and the diacritic off a letter, leaving the ascii
+/// letter it's typed from (ấ -> a, ư -> u, đ -> d)
+///
+/// Used to match letters whatever marks they carry, where `remove_accents`
+/// keeps the diacritic (ấ -> â) to tell the letters apart. Any other char
+/// comes back unchanged.
 pub fn clean_char(ch: char) -> char {
-    let accents = vec![
-        "aàảãáạăằẳẵắặâầẩẫấậ",
-        "AÀẢÃÁẠĂẰẲẴẮẶÂẦẨẪẤẬ",
-        "dđ", "DĐ",
-        "eèẻẽéẹêềểễếệ",
-        "EÈẺẼÉẸÊỀỂỄẾỆ",
-        "iìỉĩíị",
-        "IÌỈĨÍỊ",
-        "oòỏõóọôồổỗốộơờởỡớợ",
-        "OÒỎÕÓỌÔỒỔỖỐỘƠỜỞỠỚỢ",
-        "uùủũúụưừửữứự",
-        "UÙỦŨÚỤƯỪỬỮỨỰ",
-        "yỳỷỹýỵ",
-        "YỲỶỸÝỴ"
+    match ch {
+        'à' | 'ả' | 'ã' | 'á' | 'ạ' | 'ă'
+        | 'ằ' | 'ẳ' | 'ẵ' | 'ắ' | 'ặ' | 'â'
+        | 'ầ' | 'ẩ' | 'ẫ' | 'ấ' | 'ậ' => 'a',
+        'À' | 'Ả' | 'Ã' | 'Á' | 'Ạ' | 'Ă'
+        | 'Ằ' | 'Ẳ' | 'Ẵ' | 'Ắ' | 'Ặ' | 'Â'
+        | 'Ầ' | 'Ẩ' | 'Ẫ' | 'Ấ' | 'Ậ' => 'A',
+        'đ' => 'd',
+        'Đ' => 'D',
+        'è' | 'ẻ' | 'ẽ' | 'é' | 'ẹ' | 'ê'
+        | 'ề' | 'ể' | 'ễ' | 'ế' | 'ệ' => 'e',
+        'È' | 'Ẻ' | 'Ẽ' | 'É' | 'Ẹ' | 'Ê'
+        | 'Ề' | 'Ể' | 'Ễ' | 'Ế' | 'Ệ' => 'E',
+        'ì' | 'ỉ' | 'ĩ' | 'í' | 'ị' => 'i',
+        'Ì' | 'Ỉ' | 'Ĩ' | 'Í' | 'Ị' => 'I',
+        'ò' | 'ỏ' | 'õ' | 'ó' | 'ọ' | 'ô'
+        | 'ồ' | 'ổ' | 'ỗ' | 'ố' | 'ộ' | 'ơ'
+        | 'ờ' | 'ở' | 'ỡ' | 'ớ' | 'ợ' => 'o',
+        'Ò' | 'Ỏ' | 'Õ' | 'Ó' | 'Ọ' | 'Ô'
+        | 'Ồ' | 'Ổ' | 'Ỗ' | 'Ố' | 'Ộ' | 'Ơ'
+        | 'Ờ' | 'Ở' | 'Ỡ' | 'Ớ' | 'Ợ' => 'O',
+        'ù' | 'ủ' | 'ũ' | 'ú' | 'ụ' | 'ư'
+        | 'ừ' | 'ử' | 'ữ' | 'ứ' | 'ự' => 'u',
+        'Ù' | 'Ủ' | 'Ũ' | 'Ú' | 'Ụ' | 'Ư'
+        | 'Ừ' | 'Ử' | 'Ữ' | 'Ứ' | 'Ự' => 'U',
+        'ỳ' | 'ỷ' | 'ỹ' | 'ý' | 'ỵ' => 'y',
+        'Ỳ' | 'Ỷ' | 'Ỹ' | 'Ý' | 'Ỵ' => 'Y',
+        _ => ch
+    }
+}
+
+/// Whether the engine can compose with `ch`, ascii or a vietnamese letter
+/// with a tone or diacritic (ế, đ)
+///
+/// Anything else (emoji, CJK, other scripts) only ever passes through.
+pub fn is_vietnamese_char(ch: char) -> bool {
+    ch.is_ascii() || clean_char(ch) != ch
+}
+
+/// Strip the tone from a vowel but keep its diacritic (ấ -> â, ạ -> a)
+///
+/// Any other char, consonants, đ, digits, punctuation or anything outside
+/// vietnamese, comes back unchanged. `clean_char` strips the diacritic too.
+pub fn remove_accents(ch: char) -> char {
+    match ch {
+        'à' | 'ả' | 'ã' | 'á' | 'ạ' => 'a',
+        'ằ' | 'ẳ' | 'ẵ' | 'ắ' | 'ặ' => 'ă',
+        'ầ' | 'ẩ' | 'ẫ' | 'ấ' | 'ậ' => 'â',
+        'À' | 'Ả' | 'Ã' | 'Á' | 'Ạ' => 'A',
+        'Ằ' | 'Ẳ' | 'Ẵ' | 'Ắ' | 'Ặ' => 'Ă',
+        'Ầ' | 'Ẩ' | 'Ẫ' | 'Ấ' | 'Ậ' => 'Â',
+        'è' | 'ẻ' | 'ẽ' | 'é' | 'ẹ' => 'e',
+        'ề' | 'ể' | 'ễ' | 'ế' | 'ệ' => 'ê',
+        'È' | 'Ẻ' | 'Ẽ' | 'É' | 'Ẹ' => 'E',
+        'Ề' | 'Ể' | 'Ễ' | 'Ế' | 'Ệ' => 'Ê',
+        'ì' | 'ỉ' | 'ĩ' | 'í' | 'ị' => 'i',
+        'Ì' | 'Ỉ' | 'Ĩ' | 'Í' | 'Ị' => 'I',
+        'ò' | 'ỏ' | 'õ' | 'ó' | 'ọ' => 'o',
+        'ồ' | 'ổ' | 'ỗ' | 'ố' | 'ộ' => 'ô',
+        'ờ' | 'ở' | 'ỡ' | 'ớ' | 'ợ' => 'ơ',
+        'Ò' | 'Ỏ' | 'Õ' | 'Ó' | 'Ọ' => 'O',
+        'Ồ' | 'Ổ' | 'Ỗ' | 'Ố' | 'Ộ' => 'Ô',
+        'Ờ' | 'Ở' | 'Ỡ' | 'Ớ' | 'Ợ' => 'Ơ',
+        'ù' | 'ủ' | 'ũ' | 'ú' | 'ụ' => 'u',
+        'ừ' | 'ử' | 'ữ' | 'ứ' | 'ự' => 'ư',
+        'Ù' | 'Ủ' | 'Ũ' | 'Ú' | 'Ụ' => 'U',
+        'Ừ' | 'Ử' | 'Ữ' | 'Ứ' | 'Ự' => 'Ư',
+        'ỳ' | 'ỷ' | 'ỹ' | 'ý' | 'ỵ' => 'y',
+        'Ỳ' | 'Ỷ' | 'Ỹ' | 'Ý' | 'Ỵ' => 'Y',
+        _ => ch
+    }
+}
+
+const TONES: [Tone; 5] = [
+    Tone::Acute,
+    Tone::Grave,
+    Tone::HookAbove,
+    Tone::Tilde,
+    Tone::Dot,
+];
+
+// letters with a diacritic, lowercase && uppercase
+const DIACRITIC_LETTERS: [(char, Diacritic, char, char); 7] = [
+    ('a', Diacritic::Circumflex, 'â', 'Â'),
+    ('a', Diacritic::Breve, 'ă', 'Ă'),
+    ('e', Diacritic::Circumflex, 'ê', 'Ê'),
+    ('o', Diacritic::Circumflex, 'ô', 'Ô'),
+    ('o', Diacritic::Horn, 'ơ', 'Ơ'),
+    ('u', Diacritic::Horn, 'ư', 'Ư'),
+    ('d', Diacritic::CrossedD, 'đ', 'Đ'),
+];
+
+/// The built-in table putting `tone` on each vowel
+pub fn tone_map(tone: Tone) -> CharacterMap {
+    match tone {
+        Tone::Acute => character_map::ACUTE_MAP,
+        Tone::Grave => character_map::GRAVE_MAP,
+        Tone::HookAbove => character_map::HOOK_ABOVE_MAP,
+        Tone::Tilde => character_map::TILDE_MAP,
+        Tone::Dot => character_map::DOT_MAP,
+    }
+}
+
+/// Put a diacritic and a tone on `base` (e, acute, circumflex -> ế), the
+/// inverse of `decompose`
+///
+/// `None` when vietnamese has no such letter, a tone on a consonant or a
+/// diacritic the base can't take (i with a horn).
+pub fn compose_char(base: char, tone: Option<Tone>, diacritic: Option<Diacritic>)
+                    -> Option<char> {
+    let letter = match diacritic {
+        Some(diacritic) => DIACRITIC_LETTERS
+            .iter()
+            .find(|&&(letter_base, letter_diacritic, _, _)| {
+                letter_base == base.to_ascii_lowercase()
+                    && letter_diacritic == diacritic
+            })
+            .map(|&(_, _, lower, upper)| {
+                if base.is_uppercase() { upper } else { lower }
+            })?,
+        None => base,
+    };
+    match tone {
+        Some(tone) => character_map::lookup(&tone_map(tone), letter),
+        None => Some(letter),
+    }
+}
+
+/// Every letter `base` can become, plain first then with each tone, for
+/// each diacritic it takes (a -> a á à ả ã ạ â ấ ầ ẩ ẫ ậ ă ắ ằ ẳ ẵ ặ)
+///
+/// The base of a composed char is used, so ấ gives the same list as a.
+pub fn variants_of(base: char) -> Vec<char> {
+    let base = clean_char(base);
+    let diacritics = [
+        None,
+        Some(Diacritic::Circumflex),
+        Some(Diacritic::Breve),
+        Some(Diacritic::Horn),
+        Some(Diacritic::CrossedD),
     ];
-    for accent in accents {
-        let regex = Regex::new(&format!("[{}]", &accent[1..]));
-        let replace_char = accent.chars().next().unwrap();
-        if let Ok(re) = regex {
-            if re.is_match(&ch.to_string()) {
-                return replace_char;
-            }
+    let mut variants = Vec::new();
+    for &diacritic in diacritics.iter() {
+        variants.extend(compose_char(base, None, diacritic));
+        for &tone in TONES.iter() {
+            variants.extend(compose_char(base, Some(tone), diacritic));
         }
     }
-    ch
+    variants
 }
 
-pub fn remove_accents(ch: char) -> char {
-    let accents = vec![
-        "aàảãáạ",
-        "ăằẳẵắặ",
-        "âầẩẫấậ",
-        "AÀẢÃÁẠ",
-        "ĂẰẲẴẮẶ",
-        "ÂẦẨẪẤẬ",
-        "eèẻẽéẹ",
-        "êềểễếệ",
-        "EÈẺẼÉẸ",
-        "ÊỀỂỄẾỆ",
-        "iìỉĩíị",
-        "IÌỈĨÍỊ",
-        "oòỏõóọ",
-        "ôồổỗốộ",
-        "ơờởỡớợ",
-        "OÒỎÕÓỌ",
-        "ÔỒỔỖỐỘ",
-        "ƠỜỞỠỚỢ",
-        "uùủũúụ",
-        "ưừửữứự",
-        "UÙỦŨÚỤ",
-        "ƯỪỬỮỨỰ",
-        "yỳỷỹýỵ",
-        "YỲỶỸÝỴ"
+/// Split a composed char into its base letter, diacritic and tone (ế ->
+/// e, circumflex, acute)
+///
+/// Chars that aren't vietnamese letters come back as is with neither.
+pub fn decompose(ch: char) -> (char, Option<Diacritic>, Option<Tone>) {
+    let tone = TONES
+        .iter()
+        .find(|&&tone| tone_map(tone).iter().any(|&(_, toned_ch)| toned_ch == ch))
+        .copied();
+    let diacritic = match remove_accents(ch) {
+        'â' | 'ê' | 'ô' | 'Â' | 'Ê' | 'Ô' => Some(Diacritic::Circumflex),
+        'ơ' | 'ư' | 'Ơ' | 'Ư' => Some(Diacritic::Horn),
+        'ă' | 'Ă' => Some(Diacritic::Breve),
+        'đ' | 'Đ' => Some(Diacritic::CrossedD),
+        _ => None
+    };
+    (clean_char(ch), diacritic, tone)
+}
+
+/// The unicode code points of `text`, one per char (ế -> 0x1EBF)
+///
+/// Composed text from the engine is precomposed, each letter one code
+/// point, which is clearer to compare in logs than rendered glyphs.
+pub fn to_codepoints(text: &str) -> Vec<u32> {
+    text.chars().map(u32::from).collect()
+}
+
+/// Check if the chars read as the start of a vietnamese syllable: an
+/// optional onset, up to three vowels and an optional coda
+///
+/// An onset on its own (d, ngh) counts since that's where đ gets typed.
+/// The onset has to be spelled the way it's written before the vowel, c
+/// and g before a, o, u but k, gh and ngh before e, i, y (ca, ke, ghi).
+pub fn is_valid_syllable(chars: &[char]) -> bool {
+    let codas = ["ch", "ng", "nh", "c", "m", "n", "p", "t"];
+    let (onset, vowels, coda) = split_syllable(chars);
+    if vowels.is_empty() {
+        return coda.is_empty();
+    }
+    let is_front_vowel = vowels.starts_with(['e', 'i', 'y']);
+    let is_spelled_right = match onset.as_str() {
+        "c" => !is_front_vowel,
+        "k" | "gh" | "ngh" => is_front_vowel,
+        _ => true
+    };
+    is_spelled_right
+        && vowels.len() <= 3
+        && (coda.is_empty() || codas.contains(&coda.as_str()))
+}
+
+/// Whether `tone` can go on the syllable in `chars`, one closed by p, t, c
+/// or ch only takes the acute or the dot (tóp, tọp but no tòp)
+pub fn allows_tone(chars: &[char], tone: Tone) -> bool {
+    let (_, _, coda) = split_syllable(chars);
+    let is_stop = ["p", "t", "c", "ch"].contains(&coda.as_str());
+    !is_stop || matches!(tone, Tone::Acute | Tone::Dot)
+}
+
+/// Whether the chars read as a whole syllable closed by a coda (anh, tiếng),
+/// so a vowel typed after them can only start the next syllable
+pub fn ends_in_coda(chars: &[char]) -> bool {
+    let (_, vowels, coda) = split_syllable(chars);
+    !vowels.is_empty() && !coda.is_empty() && is_valid_syllable(chars)
+}
+
+// the onset, vowels and whatever follows them, lowercase and without marks
+fn split_syllable(chars: &[char]) -> (String, String, String) {
+    let onsets = [
+        "ngh", "ch", "gh", "gi", "kh", "ng", "nh", "ph", "qu", "th", "tr",
+        "b", "c", "d", "g", "h", "k", "l", "m", "n", "p", "r", "s", "t", "v",
+        "x"
     ];
-    for accent in accents {
-        let regex = Regex::new(&format!("[{}]", &accent
-                                        .chars()
-                                        .skip(1)
-                                        .collect::<String>()));
-        let replace_char = accent.chars().next().unwrap();
-        if let Ok(re) = regex {
-            if re.is_match(&ch.to_string()) {
-                return replace_char;
+    let syllable = chars
+        .iter()
+        .map(|&ch| clean_char(ch).to_ascii_lowercase())
+        .collect::<String>();
+    let onset = onsets
+        .iter()
+        .find(|onset| syllable.starts_with(*onset))
+        .map_or("", |onset| onset);
+    let rest = &syllable[onset.len()..];
+    let vowel_count = rest
+        .chars()
+        .take_while(|ch| "aeiouy".contains(*ch))
+        .count();
+    let (vowels, coda) = rest.split_at(vowel_count);
+    if onset == "gi" && vowels.is_empty() {
+        // the i of gi is the nucleus itself in gì, gìn
+        return ("g".to_string(), "i".to_string(), coda.to_string());
+    }
+    (onset.to_string(), vowels.to_string(), coda.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_accents_leaves_ascii_alone() {
+        for ch in (0..128u8).map(char::from) {
+            assert_eq!(remove_accents(ch), ch);
+        }
+    }
+
+    #[test]
+    fn remove_accents_only_strips_the_tone() {
+        for &tone in TONES.iter() {
+            for &(untoned, toned) in tone_map(tone).iter() {
+                assert_eq!(remove_accents(toned), untoned);
+                assert_eq!(remove_accents(untoned), untoned);
             }
         }
+        assert_eq!(remove_accents('ậ'), 'â');
+        assert_eq!(remove_accents('Ự'), 'Ư');
+        assert_eq!(remove_accents('đ'), 'đ');
+    }
+
+    #[test]
+    fn clean_char_strips_what_remove_accents_keeps() {
+        assert_eq!(clean_char('ấ'), 'a');
+        assert_eq!(remove_accents('ấ'), 'â');
+        assert_eq!(clean_char('Ự'), 'U');
+        assert_eq!(remove_accents('Ự'), 'Ư');
+        assert_eq!(clean_char('ơ'), 'o');
+        assert_eq!(remove_accents('ơ'), 'ơ');
+        assert_eq!(clean_char('é'), 'e');
+        assert_eq!(remove_accents('é'), 'e');
+        for ch in "bcz19-' ".chars() {
+            assert_eq!(clean_char(ch), ch);
+        }
+    }
+
+    #[test]
+    fn to_codepoints_gives_one_per_char() {
+        assert_eq!(to_codepoints("ế"), [0x1ebf]);
+        assert_eq!(to_codepoints("tiếng"), [0x74, 0x69, 0x1ebf, 0x6e, 0x67]);
+        assert_eq!(to_codepoints(""), []);
+    }
+
+    // every vowel in the order of the tone maps, untoned then the five
+    // tones, lowercase then uppercase
+    const VOWELS: [&str; 24] = [
+        "aáàảãạ", "ăắằẳẵặ", "âấầẩẫậ", "eéèẻẽẹ", "êếềểễệ", "oóòỏõọ", "ôốồổỗộ", "ơớờởỡợ",
+        "iíìỉĩị", "uúùủũụ", "ưứừửữự", "yýỳỷỹỵ", "AÁÀẢÃẠ", "ĂẮẰẲẴẶ", "ÂẤẦẨẪẬ", "EÉÈẺẼẸ",
+        "ÊẾỀỂỄỆ", "OÓÒỎÕỌ", "ÔỐỒỔỖỘ", "ƠỚỜỞỠỢ", "IÍÌỈĨỊ", "UÚÙỦŨỤ", "ƯỨỪỬỮỰ", "YÝỲỶỸỴ",
+    ];
+
+    // the tone of each column of `VOWELS`
+    const COLUMN_TONES: [Option<Tone>; 6] = [
+        None,
+        Some(Tone::Acute),
+        Some(Tone::Grave),
+        Some(Tone::HookAbove),
+        Some(Tone::Tilde),
+        Some(Tone::Dot),
+    ];
+
+    #[test]
+    fn decompose_splits_every_vowel() {
+        for row in VOWELS.iter() {
+            let untoned = row.chars().next().unwrap();
+            let diacritic = decompose(untoned).1;
+            for (ch, &tone) in row.chars().zip(COLUMN_TONES.iter()) {
+                assert_eq!(decompose(ch), (clean_char(untoned), diacritic, tone), "{}", ch);
+            }
+        }
+        assert_eq!(decompose('ặ'), ('a', Some(Diacritic::Breve), Some(Tone::Dot)));
+        assert_eq!(decompose('ợ'), ('o', Some(Diacritic::Horn), Some(Tone::Dot)));
+        assert_eq!(decompose('ữ'), ('u', Some(Diacritic::Horn), Some(Tone::Tilde)));
+        assert_eq!(decompose('Ễ'), ('E', Some(Diacritic::Circumflex), Some(Tone::Tilde)));
+        assert_eq!(decompose('đ'), ('d', Some(Diacritic::CrossedD), None));
+        assert_eq!(decompose('b'), ('b', None, None));
+        assert_eq!(decompose('1'), ('1', None, None));
+    }
+
+    #[test]
+    fn compose_char_only_gives_letters_vietnamese_has() {
+        for row in VOWELS.iter() {
+            let untoned = row.chars().next().unwrap();
+            let (base, diacritic, _) = decompose(untoned);
+            for (ch, &tone) in row.chars().zip(COLUMN_TONES.iter()) {
+                assert_eq!(compose_char(base, tone, diacritic), Some(ch));
+            }
+        }
+        let diacritics = [None, Some(Diacritic::Circumflex), Some(Diacritic::Breve),
+                          Some(Diacritic::Horn), Some(Diacritic::CrossedD)];
+        let takes = |base: char, diacritic: Option<Diacritic>| match diacritic {
+            None => true,
+            Some(Diacritic::Circumflex) => "aeo".contains(base),
+            Some(Diacritic::Breve) => base == 'a',
+            Some(Diacritic::Horn) => "ou".contains(base),
+            Some(Diacritic::CrossedD) => base == 'd',
+        };
+        for base in "abdeiouyz".chars() {
+            for &diacritic in diacritics.iter() {
+                for &tone in COLUMN_TONES.iter() {
+                    let is_letter = takes(base, diacritic)
+                        && (tone.is_none() || "aeiouy".contains(base));
+                    let composed = compose_char(base, tone, diacritic);
+                    let case = format!("{} {:?} {:?}", base, tone, diacritic);
+                    assert_eq!(composed.is_some(), is_letter, "{}", case);
+                    let upper = compose_char(base.to_ascii_uppercase(), tone, diacritic);
+                    assert_eq!(upper, composed.map(|ch| ch.to_uppercase().next().unwrap()));
+                }
+            }
+        }
+        assert_eq!(compose_char('i', None, Some(Diacritic::Horn)), None);
+        assert_eq!(compose_char('d', Some(Tone::Acute), Some(Diacritic::CrossedD)), None);
+        assert_eq!(compose_char('a', Some(Tone::Dot), Some(Diacritic::Breve)), Some('ặ'));
+    }
+
+    #[test]
+    fn variants_of_lists_every_letter_of_the_base() {
+        let expected = [
+            ('a', "aáàảãạâấầẩẫậăắằẳẵặ"),
+            ('e', "eéèẻẽẹêếềểễệ"),
+            ('o', "oóòỏõọôốồổỗộơớờởỡợ"),
+            ('u', "uúùủũụưứừửữự"),
+            ('i', "iíìỉĩị"),
+            ('y', "yýỳỷỹỵ"),
+            ('d', "dđ"),
+            ('A', "AÁÀẢÃẠÂẤẦẨẪẬĂẮẰẲẴẶ"),
+        ];
+        for &(base, variants) in expected.iter() {
+            assert_eq!(variants_of(base).into_iter().collect::<String>(), variants, "{}", base);
+        }
+        assert_eq!(variants_of('ấ'), variants_of('a'));
+        assert_eq!(variants_of('ợ'), variants_of('o'));
+        assert_eq!(variants_of('b'), ['b']);
     }
-    ch
 }
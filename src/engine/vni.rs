@@ -1,11 +1,8 @@
-use super::{PhysicKey, Action, KeyState};
+use super::{PhysicKey, Action, KeyResponse, KeyState, Tone, Diacritic, keycodes, diff_actions};
 use super::{util, character_map};
-use std::collections::HashMap;
-use std::iter::FromIterator;
-
-pub struct Vni {
-    buffer: Vec<char>
-}
+use super::character_map::CharacterMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::{error, fmt, mem};
 
 const TRIGGER_ACUTE: char = '1';
 const TRIGGER_GRAVE: char = '2';
@@ -18,19 +15,837 @@ const TRIGGER_HORN: char = '7';
 const TRIGGER_BREVE: char = '8';
 const TRIGGER_CROSSED_D: char = '9';
 
+const TRIGGER_REMOVE_TONE: char = '0';
+
+/// Where the tone goes on the oa, oe, oy and uy nuclei when nothing follows
+/// them, a coda always moves the tone to the second vowel (hoán, khoẻn)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ToneStyle {
+    /// hoà, khoẻ, thuý
+    Modern,
+    /// hòa, khỏe, thúy
+    Classic,
+    /// The tone always goes on the last vowel, whatever the syllable (hoá,
+    /// but also muá and tiéng), a simple rule for learners
+    LastVowel,
+}
+
+impl ToneStyle {
+    /// The style people using `locale` (vi_VN, vi-US.UTF-8, en_GB) most
+    /// likely expect
+    ///
+    /// Vietnamese outside Vietnam (vi_US, vi_FR, vi_AU) gets the classic
+    /// style most of the diaspora kept, vi_VN, a bare vi and any other
+    /// language get the modern style taught in Vietnam today.
+    pub fn for_locale(locale: &str) -> Self {
+        let locale = locale
+            .split(['.', '@'])
+            .next()
+            .unwrap_or("");
+        let mut parts = locale.split(['_', '-']);
+        let language = parts.next().unwrap_or("");
+        let region = parts.next();
+        match region {
+            Some(region) if language.eq_ignore_ascii_case("vi")
+                && !region.eq_ignore_ascii_case("vn") => ToneStyle::Classic,
+            _ => ToneStyle::Modern
+        }
+    }
+}
+
+/// Why a `VniConfig` can't be used, returned by `Vni::try_with_config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The custom map for `tone` has no entry for `vowel`, typing the tone
+    /// on it would do nothing
+    MissingVowel { tone: Tone, vowel: char },
+    /// The custom map for `tone` has `vowel` more than once, only the first
+    /// entry would ever be used
+    DuplicateVowel { tone: Tone, vowel: char },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::MissingVowel { tone, vowel } => {
+                write!(f, "the {:?} map has no entry for {}", tone, vowel)
+            }
+            ConfigError::DuplicateVowel { tone, vowel } => {
+                write!(f, "the {:?} map has more than one entry for {}", tone, vowel)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// How `Vni::handle_key` hands back the edits of a key typed into the
+/// syllable, see `VniConfig::output_mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The fewest backspaces and inserts fixing the document up
+    Edits,
+    /// A single `Action::ReplaceWord` with the whole syllable for every key
+    /// typed into it, for hosts that can replace a range atomically
+    ReplaceWord,
+}
+
+/// What Enter does while a syllable is composed, see `VniConfig::enter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnterBehavior {
+    /// Commit the syllable and keep the newline, like any whitespace
+    Newline,
+    /// Only commit the syllable, the newline typed is deleted again. Enter
+    /// types a newline as usual once nothing is composed.
+    Commit,
+}
+
+/// What happens to the syllable when a char would take it past
+/// `VniConfig::max_buffer_len`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Forget the oldest char, the syllable keeps the latest ones
+    DropOldest,
+    /// Commit the syllable as composed and start a new one with the char,
+    /// as if a separator had been typed without typing anything
+    CommitAndReset,
+}
+
+/// A tone or diacritic put by one of the triggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mark {
+    Acute,
+    Grave,
+    HookAbove,
+    Tilde,
+    Dot,
+    Circumflex,
+    Horn,
+    Breve,
+    CrossedD,
+    /// Not a mark, the trigger taking the tone off
+    RemoveTone,
+}
+
+impl Mark {
+    fn tone(self) -> Option<Tone> {
+        match self {
+            Mark::Acute => Some(Tone::Acute),
+            Mark::Grave => Some(Tone::Grave),
+            Mark::HookAbove => Some(Tone::HookAbove),
+            Mark::Tilde => Some(Tone::Tilde),
+            Mark::Dot => Some(Tone::Dot),
+            _ => None
+        }
+    }
+}
+
+/// How `Vni` composes
+///
+/// Triggers match the char a key types with its modifier applied
+/// (`PhysicKey::to_char`), so shift + 6 types `^` and is only the circumflex
+/// trigger when `trigger_circumflex` is set to `^`, while caps lock + 6
+/// still types the `6` trigger.
+#[derive(Debug, Clone)]
+pub struct VniConfig {
+    pub tone_style: ToneStyle,
+    /// Only compose when the buffer reads as a vietnamese syllable, so
+    /// triggers typed after words such as `file` or `jazz` stay literal
+    ///
+    /// This is the mode for typing english and vietnamese in one sentence
+    /// (`the file2 is on pho73` gives `the file2 is on phở`). A syllable
+    /// has to be spelled right (ce1 stays literal, ke1 gives ké) and one
+    /// closed by p, t, c or ch only takes the acute or the dot. English
+    /// words that still read as a syllable (bin, top) get composed like
+    /// any other, the `0` trigger takes the tone back off.
+    pub smart: bool,
+    /// Spell a word initial iê as yê (ien6 gives yên instead of iên)
+    ///
+    /// It's the only respelling the engine does and it runs after whatever
+    /// the trigger changed, the `0` trigger included. Taking the tone off
+    /// never turns a y back into an i (yến0 gives yên) nor an i into a y.
+    pub initial_ie_as_ye: bool,
+    /// Turn a doubled o into a single ô on the circumflex trigger (oo6 gives
+    /// ô, boong6 gives bông) for users used to Telex typing oo for ô. Off by
+    /// default since VNI keeps both o's, the circumflex trigger is then
+    /// typed literally after oo (xoong6 stays xoong6).
+    pub collapse_double_o: bool,
+    /// Hold a tone typed before any vowel and put it on the vowel typed
+    /// right after it (1a gives á). Any other key leaves the trigger typed
+    /// as is (1b stays 1b).
+    pub pending_tone: bool,
+    /// Typing the tone the syllable already has takes it off instead (a11
+    /// gives a), judged on the vowel as it is now so a diacritic typed in
+    /// between doesn't matter (a161 gives â). On by default, off keeps the
+    /// tone and swallows the trigger.
+    pub repeat_removes_tone: bool,
+    /// Letters that never occur in vietnamese (f, j, w, z), a syllable
+    /// holding one of them is taken as a foreign word and its triggers are
+    /// typed literally (jazz1 stays jazz1). Matched ignoring case, empty by
+    /// default.
+    pub foreign_letters: Vec<char>,
+    /// Only put the tone of a punctuation trigger (VIQR's `'` and `` ` ``)
+    /// right after a vowel of a syllable, anywhere else the trigger is typed
+    /// as punctuation so english stays as is (don't, it's). On by default,
+    /// a' gives á and ba` gives bà.
+    ///
+    /// A trigger that is also a syllable separator only ends the syllable
+    /// when it doesn't put a tone.
+    pub punctuation_tone_after_vowel: bool,
+    /// Let the keypad digits trigger marks like the digit row does. On by
+    /// default, off types them literally for users entering numbers there
+    /// (a + keypad 6 stays a6 while a6 on the digit row gives â).
+    pub keypad_triggers: bool,
+    /// Let w put the horn like the horn trigger does, for hands used to
+    /// Telex (uw gives ư, tuow gives tươ). Off by default, w is then a
+    /// letter like any other. It stays one where there's nothing to put a
+    /// horn on (bw stays bw).
+    pub w_as_horn: bool,
+    pub trigger_acute: char,
+    pub trigger_grave: char,
+    pub trigger_hook_above: char,
+    pub trigger_tilde: char,
+    pub trigger_dot: char,
+    pub trigger_circumflex: char,
+    pub trigger_horn: char,
+    pub trigger_breve: char,
+    pub trigger_crossed_d: char,
+    /// Takes the tone off the syllable, its diacritics stay (tiến0 gives
+    /// tiên). Typed literally when there's no tone.
+    ///
+    /// The letters are left as typed, mỹ0 gives my and thý0 gives thy
+    /// rather than the thi some spell it as.
+    pub trigger_remove_tone: char,
+    /// Marks that are never put, their trigger gets typed literally (a8
+    /// stays a8 with the breve disabled). Empty by default.
+    pub disabled_marks: Vec<Mark>,
+    /// Punctuation that ends a syllable inside a word, the syllable after
+    /// it composes on its own (Trung-Quoc65 gives Trung-Quốc). `-` and `'`
+    /// by default, any other punctuation is typed as part of the syllable.
+    pub syllable_separators: Vec<char>,
+    /// Chars that end the syllable like a separator but are deleted right
+    /// away, to force a boundary where the engine would compose across
+    /// (cu|a1 gives cuá where cua1 gives cúa). Empty by default, `|` is the
+    /// usual pick. No key types a zero width non-joiner, it only works as
+    /// one in text given to `Vni::handle_text`.
+    pub hidden_separators: Vec<char>,
+    /// Keycode of a key that commits the syllable as composed and starts a
+    /// new one without typing anything, for hosts that swallow that key.
+    /// It can be Enter, which then skips autocorrect and the soft commit.
+    pub commit_key: Option<u32>,
+    /// How the actions of a key typed into the syllable come back, `Edits`
+    /// by default. Keys ending the syllable (whitespace, separators) keep
+    /// giving edits, as does a trigger editing a soft committed syllable.
+    pub output_mode: OutputMode,
+    /// What Enter does while the buffer is composing (see
+    /// `Vni::is_composing`), `Newline` by default. A `commit_key` set to
+    /// Enter takes precedence.
+    pub enter: EnterBehavior,
+    /// Number of key presses after a whitespace during which a tone or
+    /// diacritic trigger still edits the syllable that whitespace committed.
+    ///
+    /// Whitespace typed inside the window is kept and typed again after the
+    /// edit, backspacing over all of it resumes composing the syllable, any
+    /// other key closes the window. 0 clears the buffer on whitespace right
+    /// away.
+    pub soft_commit_window: usize,
+    /// How many of the last key presses `Vni::recent_keys` remembers, 0
+    /// (the default) records nothing since the log holds whatever the user
+    /// typed
+    pub key_log_size: usize,
+    /// Words to replace when they get committed by a whitespace, matched
+    /// exactly as composed (case included). Empty by default.
+    pub autocorrect: HashMap<String, String>,
+    /// Syllables considered vietnamese by `revert_if_not_in_dict`, matched
+    /// ignoring case. Empty by default.
+    pub dictionary: HashSet<String>,
+    /// Take the marks back off a syllable that isn't in `dictionary` when
+    /// whitespace, a separator or the commit key commits it, so english
+    /// the engine composed anyway goes back to plain letters (tóp gives top
+    /// without top in the dictionary). Off by default.
+    pub revert_if_not_in_dict: bool,
+    /// Tables replacing the built-in one of a tone, each mapping the 24
+    /// untoned vowels in any order (see `character_map`). Empty by default.
+    ///
+    /// Tones are only recognized again (to move, repeat or take them off)
+    /// on the vietnamese letters of the built-in tables.
+    pub tone_maps: Vec<(Tone, CharacterMap)>,
+    /// Most chars the syllable being composed holds, 0 (the default) for no
+    /// limit. Long runs without whitespace (urls, paths) otherwise keep
+    /// growing the buffer.
+    pub max_buffer_len: usize,
+    /// What to do with a char that doesn't fit, `DropOldest` by default
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for VniConfig {
+    fn default() -> Self {
+        Self {
+            tone_style: ToneStyle::Modern,
+            smart: false,
+            initial_ie_as_ye: false,
+            collapse_double_o: false,
+            pending_tone: false,
+            repeat_removes_tone: true,
+            foreign_letters: Vec::new(),
+            punctuation_tone_after_vowel: true,
+            keypad_triggers: true,
+            w_as_horn: false,
+            trigger_acute: TRIGGER_ACUTE,
+            trigger_grave: TRIGGER_GRAVE,
+            trigger_hook_above: TRIGGER_HOOK_ABOVE,
+            trigger_tilde: TRIGGER_TILDE,
+            trigger_dot: TRIGGER_DOT,
+            trigger_circumflex: TRIGGER_CIRCUMFLEX,
+            trigger_horn: TRIGGER_HORN,
+            trigger_breve: TRIGGER_BREVE,
+            trigger_crossed_d: TRIGGER_CROSSED_D,
+            trigger_remove_tone: TRIGGER_REMOVE_TONE,
+            disabled_marks: Vec::new(),
+            syllable_separators: vec!['-', '\''],
+            hidden_separators: Vec::new(),
+            commit_key: None,
+            output_mode: OutputMode::Edits,
+            enter: EnterBehavior::Newline,
+            soft_commit_window: 0,
+            key_log_size: 0,
+            autocorrect: HashMap::new(),
+            dictionary: HashSet::new(),
+            revert_if_not_in_dict: false,
+            tone_maps: Vec::new(),
+            max_buffer_len: 0,
+            overflow_policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// Chainable way to build a configured `Vni`, every option that isn't set
+/// keeps the value used by `Vni::new()`
+///
+/// ```
+/// use vi_rs::engine::{Vni, ToneStyle};
+///
+/// let vni = Vni::builder()
+///     .tone_style(ToneStyle::Classic)
+///     .trigger_acute('1')
+///     .smart(true)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VniBuilder {
+    config: VniConfig,
+}
+
+impl VniBuilder {
+    pub fn tone_style(mut self, tone_style: ToneStyle) -> Self {
+        self.config.tone_style = tone_style;
+        self
+    }
+
+    pub fn smart(mut self, smart: bool) -> Self {
+        self.config.smart = smart;
+        self
+    }
+
+    pub fn initial_ie_as_ye(mut self, enabled: bool) -> Self {
+        self.config.initial_ie_as_ye = enabled;
+        self
+    }
+
+    pub fn collapse_double_o(mut self, enabled: bool) -> Self {
+        self.config.collapse_double_o = enabled;
+        self
+    }
+
+    pub fn pending_tone(mut self, enabled: bool) -> Self {
+        self.config.pending_tone = enabled;
+        self
+    }
+
+    pub fn repeat_removes_tone(mut self, enabled: bool) -> Self {
+        self.config.repeat_removes_tone = enabled;
+        self
+    }
+
+    pub fn foreign_letters(mut self, letters: Vec<char>) -> Self {
+        self.config.foreign_letters = letters;
+        self
+    }
+
+    pub fn punctuation_tone_after_vowel(mut self, enabled: bool) -> Self {
+        self.config.punctuation_tone_after_vowel = enabled;
+        self
+    }
+
+    pub fn keypad_triggers(mut self, enabled: bool) -> Self {
+        self.config.keypad_triggers = enabled;
+        self
+    }
+
+    pub fn w_as_horn(mut self, enabled: bool) -> Self {
+        self.config.w_as_horn = enabled;
+        self
+    }
+
+    /// Type the tones with the Telex letters, s f r x j and z to take the
+    /// tone off, while the diacritics keep their VNI digits (tieng6s gives
+    /// tiếng)
+    ///
+    /// A tone letter only puts a tone once the syllable has a vowel, so it
+    /// types the letter at the start of a syllable (sao, xin) and wherever
+    /// there's no vowel to take it. With `smart` it's also typed as a
+    /// letter after a syllable that isn't vietnamese. Typing the same tone
+    /// letter again takes the tone off and types the letter (ass gives as,
+    /// bass gives bas).
+    pub fn telex_tones(mut self) -> Self {
+        self.config.trigger_acute = 's';
+        self.config.trigger_grave = 'f';
+        self.config.trigger_hook_above = 'r';
+        self.config.trigger_tilde = 'x';
+        self.config.trigger_dot = 'j';
+        self.config.trigger_remove_tone = 'z';
+        self
+    }
+
+    pub fn trigger_acute(mut self, ch: char) -> Self {
+        self.config.trigger_acute = ch;
+        self
+    }
+
+    pub fn trigger_grave(mut self, ch: char) -> Self {
+        self.config.trigger_grave = ch;
+        self
+    }
+
+    pub fn trigger_hook_above(mut self, ch: char) -> Self {
+        self.config.trigger_hook_above = ch;
+        self
+    }
+
+    pub fn trigger_tilde(mut self, ch: char) -> Self {
+        self.config.trigger_tilde = ch;
+        self
+    }
+
+    pub fn trigger_dot(mut self, ch: char) -> Self {
+        self.config.trigger_dot = ch;
+        self
+    }
+
+    pub fn trigger_circumflex(mut self, ch: char) -> Self {
+        self.config.trigger_circumflex = ch;
+        self
+    }
+
+    pub fn trigger_horn(mut self, ch: char) -> Self {
+        self.config.trigger_horn = ch;
+        self
+    }
+
+    pub fn trigger_breve(mut self, ch: char) -> Self {
+        self.config.trigger_breve = ch;
+        self
+    }
+
+    pub fn trigger_crossed_d(mut self, ch: char) -> Self {
+        self.config.trigger_crossed_d = ch;
+        self
+    }
+
+    pub fn trigger_remove_tone(mut self, ch: char) -> Self {
+        self.config.trigger_remove_tone = ch;
+        self
+    }
+
+    pub fn disable_mark(mut self, mark: Mark) -> Self {
+        self.config.disabled_marks.push(mark);
+        self
+    }
+
+    pub fn syllable_separators(mut self, chars: Vec<char>) -> Self {
+        self.config.syllable_separators = chars;
+        self
+    }
+
+    pub fn hidden_separators(mut self, chars: Vec<char>) -> Self {
+        self.config.hidden_separators = chars;
+        self
+    }
+
+    pub fn commit_key(mut self, keycode: u32) -> Self {
+        self.config.commit_key = Some(keycode);
+        self
+    }
+
+    pub fn output_mode(mut self, mode: OutputMode) -> Self {
+        self.config.output_mode = mode;
+        self
+    }
+
+    pub fn enter(mut self, behavior: EnterBehavior) -> Self {
+        self.config.enter = behavior;
+        self
+    }
+
+    pub fn soft_commit_window(mut self, keys: usize) -> Self {
+        self.config.soft_commit_window = keys;
+        self
+    }
+
+    pub fn key_log_size(mut self, keys: usize) -> Self {
+        self.config.key_log_size = keys;
+        self
+    }
+
+    pub fn autocorrect(mut self, words: HashMap<String, String>) -> Self {
+        self.config.autocorrect = words;
+        self
+    }
+
+    pub fn dictionary(mut self, syllables: HashSet<String>) -> Self {
+        self.config.dictionary = syllables;
+        self
+    }
+
+    pub fn revert_if_not_in_dict(mut self, enabled: bool) -> Self {
+        self.config.revert_if_not_in_dict = enabled;
+        self
+    }
+
+    pub fn tone_map(mut self, tone: Tone, map: CharacterMap) -> Self {
+        self.config.tone_maps.push((tone, map));
+        self
+    }
+
+    pub fn max_buffer_len(mut self, len: usize) -> Self {
+        self.config.max_buffer_len = len;
+        self
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.config.overflow_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Vni {
+        Vni::with_config(self.config)
+    }
+
+    /// Build after checking the config, see `Vni::try_with_config`
+    pub fn try_build(self) -> Result<Vni, ConfigError> {
+        Vni::try_with_config(self.config)
+    }
+}
+
+/// A char of the syllable changed by a trigger, given to the observer set
+/// with `Vni::set_observer`
+///
+/// Typing a tone the syllable already has takes it back off, that shows up
+/// as a transformation too (á -> a on a second `1`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Transformation {
+    /// Position of the char in the syllable
+    pub index: usize,
+    pub old: char,
+    pub new: char,
+    /// The char typed that made the change, the picked variant for
+    /// `Vni::apply_variant`
+    pub trigger: char,
+}
+
+/// Callback given each `Transformation`, see `Vni::set_observer`
+pub type Observer = Box<dyn FnMut(&Transformation) + Send>;
+
+pub struct Vni {
+    buffer: Vec<char>,
+    config: VniConfig,
+    soft_commit: Option<SoftCommit>,
+    key_log: VecDeque<char>,
+    after_context: Vec<char>,
+    pending_actions: VecDeque<Action>,
+    modern_votes: usize,
+    classic_votes: usize,
+    // style of the tone placed by the previous key, if it depended on one
+    placed_style: Option<ToneStyle>,
+    // tone trigger typed before any vowel, waiting for one
+    pending_tone: Option<char>,
+    observer: Option<Observer>,
+    // transformations of the key being explained by handle_key_explained
+    explained: Option<Vec<Transformation>>,
+}
+
+/// The syllable being composed and everything the next key depends on,
+/// taken by `Vni::snapshot` and put back by `Vni::restore`
+///
+/// The config, the key log and the style votes aren't part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VniSnapshot {
+    buffer: Vec<char>,
+    after_context: Vec<char>,
+    soft_commit: Option<SoftCommit>,
+    placed_style: Option<ToneStyle>,
+    pending_tone: Option<char>,
+}
+
+// A syllable that was committed by whitespace but can still be edited
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct SoftCommit {
+    buffer: Vec<char>,
+    trailing: Vec<char>, // whitespace typed after the syllable
+    keys_left: usize,
+}
+
+// Set of lowercase ascii letters kept as a bitmask so checking a pair is a
+// single lookup, the diacritic triggers check one for every buffered char
+#[derive(Clone, Copy)]
+struct PairSet(u32);
+
+impl PairSet {
+    fn new(chars: &[char]) -> Self {
+        let mask = chars
+            .iter()
+            .filter(|ch| ch.is_ascii_lowercase())
+            .fold(0, |mask, &ch| mask | 1 << (ch as u32 - 'a' as u32));
+        Self(mask)
+    }
+
+    fn contains(self, ch: char) -> bool {
+        ch.is_ascii_lowercase() && self.0 & 1 << (ch as u32 - 'a' as u32) != 0
+    }
+}
+
 struct DiacriticMatch {
     pub ch: char,
-    pub pair_with: Vec<char>,
+    pub pair_with: PairSet,
     pub replace_with: (char, char), // lowercase && uppercase
 }
 
+/// Put the tone `ch` carries, if any, on `replace_ch`
+fn keep_tone(ch: char, replace_ch: char) -> char {
+    let maps = [
+        character_map::ACUTE_MAP,
+        character_map::GRAVE_MAP,
+        character_map::HOOK_ABOVE_MAP,
+        character_map::TILDE_MAP,
+        character_map::DOT_MAP,
+    ];
+    for map in maps.iter() {
+        if map.iter().any(|&(_, toned_ch)| toned_ch == ch) {
+            return character_map::lookup(map, replace_ch).unwrap_or(replace_ch);
+        }
+    }
+    replace_ch
+}
+
+/// Apply the default VNI `triggers` in order to the single letter `base`
+/// (a, 6, 1 -> ấ), without any of the syllable rules, for checking the
+/// letter tables
+///
+/// Like in a syllable a trigger giving the mark the letter already has
+/// takes it back off (a, 1, 1 -> a) and `0` takes the tone off. A trigger
+/// the letter can't take (i, 7) or that isn't one (b) is skipped.
+pub fn compose_from_triggers(base: char, triggers: &[char]) -> char {
+    let (letter, mut diacritic, mut tone) = util::decompose(base);
+    for &trigger in triggers {
+        let mut next_tone = tone;
+        let mut next_diacritic = diacritic;
+        let toggle_tone = |new: Tone| Some(new).filter(|&new| tone != Some(new));
+        let toggle_diacritic =
+            |new: Diacritic| Some(new).filter(|&new| diacritic != Some(new));
+        match trigger {
+            TRIGGER_ACUTE => next_tone = toggle_tone(Tone::Acute),
+            TRIGGER_GRAVE => next_tone = toggle_tone(Tone::Grave),
+            TRIGGER_HOOK_ABOVE => next_tone = toggle_tone(Tone::HookAbove),
+            TRIGGER_TILDE => next_tone = toggle_tone(Tone::Tilde),
+            TRIGGER_DOT => next_tone = toggle_tone(Tone::Dot),
+            TRIGGER_CIRCUMFLEX => next_diacritic = toggle_diacritic(Diacritic::Circumflex),
+            TRIGGER_HORN => next_diacritic = toggle_diacritic(Diacritic::Horn),
+            TRIGGER_BREVE => next_diacritic = toggle_diacritic(Diacritic::Breve),
+            TRIGGER_CROSSED_D => next_diacritic = toggle_diacritic(Diacritic::CrossedD),
+            TRIGGER_REMOVE_TONE => next_tone = None,
+            _ => continue
+        }
+        if util::compose_char(letter, next_tone, next_diacritic).is_some() {
+            tone = next_tone;
+            diacritic = next_diacritic;
+        }
+    }
+    util::compose_char(letter, tone, diacritic).unwrap_or(base)
+}
+
+fn tone_name(tone: Tone) -> &'static str {
+    match tone {
+        Tone::Acute => "acute",
+        Tone::Grave => "grave",
+        Tone::HookAbove => "hook above",
+        Tone::Tilde => "tilde",
+        Tone::Dot => "dot",
+    }
+}
+
+fn diacritic_name(diacritic: Diacritic) -> &'static str {
+    match diacritic {
+        Diacritic::Circumflex => "circumflex",
+        Diacritic::Horn => "horn",
+        Diacritic::Breve => "breve",
+        Diacritic::CrossedD => "crossed d",
+    }
+}
+
+/// A line telling which marks `transformation` put, took off or replaced
+fn explain(transformation: &Transformation) -> String {
+    let Transformation { index, old, new, trigger } = *transformation;
+    let (_, old_diacritic, old_tone) = util::decompose(old);
+    let (_, new_diacritic, new_tone) = util::decompose(new);
+    let change = |old_mark: Option<&str>, new_mark: Option<&str>| match (old_mark, new_mark) {
+        (None, Some(new_mark)) => Some(format!("put the {} on", new_mark)),
+        (Some(old_mark), None) => Some(format!("took the {} off", old_mark)),
+        (Some(old_mark), Some(new_mark)) if old_mark != new_mark => {
+            Some(format!("replaced the {} with the {} on", old_mark, new_mark))
+        }
+        _ => None
+    };
+    let changes = [
+        change(old_diacritic.map(diacritic_name), new_diacritic.map(diacritic_name)),
+        change(old_tone.map(tone_name), new_tone.map(tone_name)),
+    ];
+    let what = changes
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" and ");
+    let what = if what.is_empty() { "respelled".to_string() } else { what };
+    let letter = util::remove_accents(old);
+    format!("{}: {} {} at {} ({} -> {})", trigger, what, letter, index, old, new)
+}
+
+/// Which style the tone on an open oa, oe, oy or uy nucleus ending `chars`
+/// follows, if there's such a nucleus with a tone
+fn placement_style(chars: &[char]) -> Option<ToneStyle> {
+    if chars.len() < 2 {
+        return None;
+    }
+    let glide = chars[chars.len() - 2];
+    let vowel = chars[chars.len() - 1];
+    let glide_no_accent = util::remove_accents(glide);
+    let vowel_no_accent = util::remove_accents(vowel);
+    let after_q = chars.len() > 2
+        && chars[chars.len() - 3].eq_ignore_ascii_case(&'q');
+    let is_open_nucleus = match glide_no_accent.to_ascii_lowercase() {
+        'o' => "aey".contains(vowel_no_accent.to_ascii_lowercase()),
+        'u' => vowel_no_accent.eq_ignore_ascii_case(&'y') && !after_q,
+        _ => false
+    };
+    if !is_open_nucleus {
+        None
+    } else if glide != glide_no_accent {
+        Some(ToneStyle::Classic)
+    } else if vowel != vowel_no_accent {
+        Some(ToneStyle::Modern)
+    } else {
+        None
+    }
+}
+
+impl Default for Vni {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Vni {
     pub fn new() -> Self {
+        Self::with_config(VniConfig::default())
+    }
+
+    /// A `Vni` with the tone style expected for `locale`, see
+    /// `ToneStyle::for_locale`
+    pub fn with_locale(locale: &str) -> Self {
+        Self::builder()
+            .tone_style(ToneStyle::for_locale(locale))
+            .build()
+    }
+
+    /// A `Vni` continuing from `text` already in the document, its last
+    /// word becomes the syllable being composed
+    ///
+    /// The tone style is guessed from the last word of `text` placing the
+    /// tone on an open oa, oe, oy or uy (hòa gives the classic style, hoà
+    /// the modern one). Without such a word the default, modern, is kept.
+    pub fn from_composed_text(text: &str) -> Self {
+        let tone_style = text
+            .split(|ch: char| !ch.is_alphabetic())
+            .rev()
+            .find_map(|word| placement_style(&word.chars().collect::<Vec<char>>()))
+            .unwrap_or(ToneStyle::Modern);
+        let mut vni = Self::builder().tone_style(tone_style).build();
+        vni.set_context(text, "");
+        vni
+    }
+
+    pub fn builder() -> VniBuilder {
+        VniBuilder::default()
+    }
+
+    pub fn with_config(config: VniConfig) -> Self {
         Self {
-            buffer: Vec::new()
+            buffer: Vec::new(),
+            config,
+            soft_commit: None,
+            key_log: VecDeque::new(),
+            after_context: Vec::new(),
+            pending_actions: VecDeque::new(),
+            modern_votes: 0,
+            classic_votes: 0,
+            placed_style: None,
+            pending_tone: None,
+            observer: None,
+            explained: None,
         }
     }
 
+    /// A `Vni` with `config` once its custom tone maps are checked to map
+    /// each of the 24 untoned vowels exactly once
+    ///
+    /// `with_config` takes the config as is, a bad map then leaves the tone
+    /// off the vowels it misses.
+    pub fn try_with_config(config: VniConfig) -> Result<Self, ConfigError> {
+        for &(tone, ref map) in &config.tone_maps {
+            for &(vowel, _) in util::tone_map(tone).iter() {
+                match map.iter().filter(|&&(base_ch, _)| base_ch == vowel).count() {
+                    0 => return Err(ConfigError::MissingVowel { tone, vowel }),
+                    1 => {}
+                    _ => return Err(ConfigError::DuplicateVowel { tone, vowel }),
+                }
+            }
+        }
+        Ok(Self::with_config(config))
+    }
+
+    /// Switch to the config of `profile`, when the host moves focus to an
+    /// app with other settings
+    ///
+    /// With `commit` the syllable is committed as composed first, like on
+    /// an arrow key. Without it the syllable is kept and the next keys
+    /// compose it further with the new config.
+    pub fn apply_profile(&mut self, profile: &VniConfig, commit: bool) {
+        if commit {
+            self.set_buffer("");
+            self.placed_style = None;
+            self.pending_tone = None;
+        }
+        self.config = profile.clone();
+    }
+
+    /// The config in effect, for showing or saving the settings
+    pub fn config(&self) -> &VniConfig {
+        &self.config
+    }
+
     fn replace_char_at(&mut self, index: usize, ch: char, is_first_edit: bool)
                        -> Vec<Action> {
         let buffer_len = self.buffer.len();
@@ -54,6 +869,45 @@ impl Vni {
         steps
     }
 
+    /// Take the diacritic of `matches` back off every char that has it,
+    /// keeping tones, so pressing the same trigger twice undoes it (u77 ->
+    /// u, uo77 -> uo)
+    fn remove_diacritic(&mut self, matches: &[DiacriticMatch]) -> Vec<Action> {
+        let mut steps: Vec<Action> = Vec::new();
+        let mut is_first_match = true;
+        for i in 0..self.buffer.len() {
+            let ch = self.buffer[i];
+            let ch_no_accent = util::remove_accents(ch);
+            for diacritic_match in matches {
+                let (lower, upper) = diacritic_match.replace_with;
+                if ch_no_accent != lower && ch_no_accent != upper {
+                    continue;
+                }
+                let base_ch = if ch_no_accent == upper {
+                    diacritic_match.ch.to_ascii_uppercase()
+                } else {
+                    diacritic_match.ch
+                };
+                let replace_char = keep_tone(ch, base_ch);
+                steps.extend(self.replace_char_at(i, replace_char, is_first_match));
+                self.buffer[i] = replace_char;
+                is_first_match = false;
+            }
+        }
+        steps
+    }
+
+    /// Add the diacritic, or take it back off if every char that would
+    /// get it already has it, so tư + o7 gives tươ while ươ + 7 gives uo
+    fn add_diacritic(&mut self, matches: Vec<DiacriticMatch>) -> Vec<Action> {
+        let old_buffer = self.buffer.clone();
+        let added = self.put_diacritic(&matches);
+        if self.buffer != old_buffer {
+            return added;
+        }
+        self.remove_diacritic(&matches)
+    }
+
     /// Add diacritic (6789)
     /// 
     /// Loop through a list of predefined diacritic match
@@ -63,202 +917,2263 @@ impl Vni {
     /// if user type aq6, it will not do anything because
     /// q is not in a list to pair with a
     ///
+    /// The diacritic goes on one char, the last one pairing with the char
+    /// after it or else the last char of the buffer (tie6ng), except for a
+    /// horn on uo which goes on both (ươ). The u of qu is part of the
+    /// onset and never takes it (quo7 gives quơ). Of several o only one
+    /// takes it, oao isn't a nucleus but oao6 gives oaô all the same.
+    ///
+    /// A horn goes on an o ending the buffer (cho7 gives chơ, do7 gives dơ)
+    /// or followed by i, m, n, p, t, c or y (hơi, lớp), but never on the o
+    /// of ong, which only takes one in ương.
+    ///
     /// return a list of actions to send to keyboard
-    fn add_diacritic(&mut self, matches: Vec<DiacriticMatch>) -> Vec<Action> {
+    fn put_diacritic(&mut self, matches: &[DiacriticMatch]) -> Vec<Action> {
         let buffer_len = self.buffer.len();
-        let mut steps: Vec<Action> = Vec::new();
-        let mut is_first_match = true;
+        let base_of = |ch: char| util::clean_char(ch).to_ascii_lowercase();
+        let mut paired = None;
+        let mut at_end = None;
         for i in 0..buffer_len {
+            let clean_ch = base_of(self.buffer[i]);
+            if clean_ch == 'u' && i > 0 && base_of(self.buffer[i - 1]) == 'q' {
+                continue;
+            }
+            for diacritic_match in matches {
+                if diacritic_match.ch != clean_ch {
+                    continue;
+                }
+                // there's no ơng, the o of ong takes a horn only as part of
+                // ương (bong7 stays as typed, thuong7 gives thương)
+                let is_ong = diacritic_match.replace_with.0 == 'ơ'
+                    && i + 2 < buffer_len
+                    && base_of(self.buffer[i + 1]) == 'n'
+                    && base_of(self.buffer[i + 2]) == 'g';
+                // ia never takes a circumflex, iê is typed ie6 (bia6 stays
+                // bia6), the i of gi is the onset though (gia6y gives giây)
+                let is_ia = diacritic_match.replace_with.0 == 'â'
+                    && i > 0
+                    && base_of(self.buffer[i - 1]) == 'i'
+                    && !(i > 1 && base_of(self.buffer[i - 2]) == 'g');
+                if is_ia {
+                    continue;
+                }
+                if i + 1 == buffer_len {
+                    at_end = Some((i, diacritic_match));
+                } else if diacritic_match.pair_with.contains(base_of(self.buffer[i + 1]))
+                    && !is_ong {
+                    paired = Some((i, diacritic_match));
+                }
+            }
+        }
+        let (index, diacritic_match) = match paired.or(at_end) {
+            Some(target) => target,
+            None => return Vec::new(),
+        };
+        let mut targets = vec![(index, diacritic_match)];
+        if diacritic_match.replace_with.0 == 'ư' || diacritic_match.replace_with.0 == 'ơ' {
+            let pair_index = if diacritic_match.ch == 'u' {
+                Some(index + 1).filter(|&i| i < buffer_len)
+            } else {
+                index.checked_sub(1)
+            };
+            let pair_ch = if diacritic_match.ch == 'u' { 'o' } else { 'u' };
+            let is_after_q = |i: usize| i > 0 && base_of(self.buffer[i - 1]) == 'q';
+            let pair = pair_index
+                .filter(|&i| base_of(self.buffer[i]) == pair_ch && !is_after_q(i))
+                .and_then(|i| {
+                    matches
+                        .iter()
+                        .find(|other_match| other_match.ch == pair_ch)
+                        .map(|other_match| (i, other_match))
+                });
+            targets.extend(pair);
+        }
+        let mut steps: Vec<Action> = Vec::new();
+        for (i, diacritic_match) in targets {
             let ch = self.buffer[i];
-            let next_ch = if i + 1 == buffer_len {
-                self.buffer[i]
+            let replace_char = if ch.is_uppercase() {
+                diacritic_match.replace_with.1
             } else {
-                self.buffer[i + 1]
+                diacritic_match.replace_with.0
             };
-            let clean_ch = util::clean_char(ch);
-            for diacritic_match in &matches {
-                if diacritic_match.ch == clean_ch.to_ascii_lowercase() {
-                    let next_ch_lower = &util::clean_char(
-                        next_ch.to_ascii_lowercase()
-                    );
-                    if diacritic_match.pair_with.contains(next_ch_lower)
-                        || i + 1 == buffer_len {
-                        let replace_char = if ch.is_ascii_uppercase() {
-                            diacritic_match.replace_with.1
-                        } else {
-                            diacritic_match.replace_with.0
-                        };
-                        steps = [
-                            steps,
-                            self.replace_char_at(i, replace_char, is_first_match)
-                        ].concat();
-                        self.buffer[i] = replace_char;
-                        if is_first_match {
-                            is_first_match = false;
-                        }
-                    }
-                }
+            // a16 and a61 should both give ấ
+            let replace_char = keep_tone(ch, replace_char);
+            // a match giving a letter vietnamese doesn't have leaves the
+            // char alone rather than type nonsense
+            let (base, diacritic, _) = util::decompose(replace_char);
+            if diacritic.is_none() || base.to_ascii_lowercase() != diacritic_match.ch {
+                continue;
             }
+            steps.extend(self.replace_char_at(i, replace_char, steps.is_empty()));
+            self.buffer[i] = replace_char;
         }
         steps
     }
 
     // Get the vowel to put the accent on
     //
-    // The rule:
-    // - If there's ơ put on top of it
-    // - otherwise if there's anything with diacritic (hat) put on top of it
-    // - otherwise if there's anything go with such as
-    //  - oa oe oo oy then put on top of it
-    // - otherwise if there's gi then put on top of the next char
-    // - otherwise what vowel come first, put it on
-    //  - a e i o u y
+    // The rule, looking at the run of vowels (the nucleus) after the onset:
+    // - u after q and i after gi belong to the onset when a vowel follows
+    //   them (quý, giếng), a lone gi keeps its i (gì) while a lone qu has no
+    //   vowel at all
+    // - If there's a vowel with diacritic (hat) put on top of it, the last
+    //   one for ươ (thường), the ô of uô (buồn, luồng)
+    // - otherwise if a consonant follows the nucleus put it on the last
+    //   vowel (hoàn, nghiễng)
+    // - otherwise with three vowels put it on the middle one (khoái, ngoáy),
+    //   uây has its â in the middle anyway (khuấy)
+    // - otherwise oa oe oy uy depend on the tone style (hoà or hòa)
+    // - otherwise put it on the first vowel (mùa, bìa, tòi)
+    //
+    // The LastVowel style skips all of it and takes the last vowel.
+    //
+    // Vowels are told apart on their clean_char base, đ becomes d there and
+    // is never a vowel (đa1 gives đá, đ1 stays literal).
     fn get_vowel_for_accent(&self) -> Option<(char, usize)> {
-        let buffer_len = self.buffer.len();
-        let diacritic_chars = ['ê', 'â', 'ô', 'ă', 'ư', 'Ê', 'Â', 'Ô', 'Ă', 'Ư'];
-        let pair_with_o_chars = ['a', 'e', 'o', 'y', 'A', 'E', 'O', 'Y'];
-        let mut vowel_positions = HashMap::new();
-        vowel_positions.insert('a', 5);
-        vowel_positions.insert('e', 4);
-        vowel_positions.insert('i', 3);
-        vowel_positions.insert('o', 2);
-        vowel_positions.insert('u', 1);
-        vowel_positions.insert('y', 0);
-
-        vowel_positions.insert('A', 5);
-        vowel_positions.insert('E', 4);
-        vowel_positions.insert('I', 3);
-        vowel_positions.insert('O', 2);
-        vowel_positions.insert('U', 1);
-        vowel_positions.insert('Y', 0);
-        let mut max_vowel_position = -1;
-        let mut max_vowel_index = 0;
-        let mut result_vowel = None;
-        for (idx, &ch) in self.buffer.iter().enumerate() {
-            let ch_no_accent = util::remove_accents(ch);
-            if ch_no_accent == 'ơ' || ch_no_accent == 'Ơ' {
-                return Some((ch_no_accent, idx));
-            } else if diacritic_chars.contains(&ch_no_accent) {
-                result_vowel = Some((ch_no_accent, idx));
-            } else if ch_no_accent == 'o'
-                && idx + 1 < buffer_len
-                && pair_with_o_chars.contains(&self.buffer[idx + 1].clone()) {
-                let next_ch = self.buffer[idx + 1];
-                return Some((next_ch, idx + 1));
-            } else if ch_no_accent == 'g' && idx + 2 < buffer_len {
-                if self.buffer[idx + 1] == 'i' {
-                    let next_ch = self.buffer[idx + 2];
-                    return Some((next_ch, idx + 2));
-                }
-            } else {
-                let vowel_position = vowel_positions.get(&ch_no_accent);
-                if let Some(&position) = vowel_position {
-                    if position > max_vowel_position {
-                        max_vowel_position = position;
-                        max_vowel_index = idx;
-                    }
-                }
+        // the context after the caret is read but never edited
+        let chars = self.buffer
+            .iter()
+            .chain(self.after_context.iter())
+            .copied()
+            .collect::<Vec<char>>();
+        self.find_vowel_for_accent(&chars)
+            .filter(|&(_, idx)| idx < self.buffer.len())
+    }
+
+    fn find_vowel_for_accent(&self, chars: &[char]) -> Option<(char, usize)> {
+        let diacritic_chars = ['ê', 'â', 'ô', 'ă', 'ư', 'ơ', 'Ê', 'Â', 'Ô', 'Ă', 'Ư', 'Ơ'];
+        let base_of = |idx: usize| util::clean_char(chars[idx]).to_ascii_lowercase();
+        let is_vowel = |idx: usize| "aeiouy".contains(base_of(idx));
+        // the u of qu is part of the onset rather than a vowel
+        let is_qu = |idx: usize| idx > 0 && base_of(idx - 1) == 'q' && base_of(idx) == 'u';
+        if self.config.tone_style == ToneStyle::LastVowel {
+            let idx = (0..chars.len()).rev().find(|&idx| is_vowel(idx))?;
+            if is_qu(idx) {
+                return None;
             }
+            return Some((chars[idx], idx));
         }
-        if result_vowel != None {
-            return result_vowel;
-        } else if max_vowel_position >= 0 {
-            let ch = self.buffer[max_vowel_index];
-            return Some((ch, max_vowel_index));
+        let mut start = (0..chars.len()).find(|&idx| is_vowel(idx))?;
+        // with no vowel after it the i of gi is the nucleus and keeps the
+        // tone (gì, gí)
+        let is_onset_glide = start > 0
+            && start + 1 < chars.len()
+            && is_vowel(start + 1)
+            && matches!((base_of(start - 1), base_of(start)), ('q', 'u') | ('g', 'i'));
+        if is_onset_glide {
+            start += 1;
+        } else if is_qu(start) {
+            // qu on its own is only an onset, there's no vowel to take the
+            // tone yet (qu1 stays literal)
+            return None;
         }
-        None
+        let end = (start..chars.len())
+            .find(|&idx| !is_vowel(idx))
+            .unwrap_or(chars.len());
+        let has_coda = end < chars.len();
+
+        let idx = if let Some(idx) = (start..end)
+            .rev()
+            .find(|&idx| diacritic_chars.contains(&util::remove_accents(chars[idx]))) {
+            idx
+        } else if end - start == 1 {
+            start
+        } else if has_coda {
+            end - 1
+        } else if end - start >= 3 {
+            start + 1
+        } else {
+            // a pair without a coda takes it on its first vowel, a toned
+            // vowel typed again being a second vowel rather than a toggle
+            // (a1a gives áa, another 2 gives àa)
+            let is_style_dependent = matches!(
+                (base_of(start), base_of(start + 1)),
+                ('o', 'a') | ('o', 'e') | ('o', 'y') | ('u', 'y')
+            );
+            if is_style_dependent && self.config.tone_style == ToneStyle::Modern {
+                start + 1
+            } else {
+                start
+            }
+        };
+        Some((chars[idx], idx))
+    }
+
+    /// The table putting `tone`, the custom one if the config has one
+    fn tone_map(&self, tone: Tone) -> CharacterMap {
+        self.config.tone_maps
+            .iter()
+            .find(|&&(map_tone, _)| map_tone == tone)
+            .map_or_else(|| util::tone_map(tone), |&(_, map)| map)
     }
 
-    fn add_accent(&mut self, map: [(char, char); 24]) -> Vec<Action> {
+    fn add_accent(&mut self, map: CharacterMap) -> Vec<Action> {
         let vowel = self.get_vowel_for_accent();
         if let Some(v) = vowel {
             let ch = v.0;
             let index = v.1;
-            let iter = map.iter().copied();
-            let replace_ch = HashMap::<char, char>::from_iter(iter)[&ch];
-            return self.replace_char_at(index, replace_ch, true);
+            let base_ch = util::remove_accents(ch);
+            let replace_ch = match character_map::lookup(&map, base_ch) {
+                Some(replace_ch) if replace_ch == ch && self.config.repeat_removes_tone => {
+                    base_ch
+                }
+                Some(replace_ch) => replace_ch,
+                None => return vec![],
+            };
+            // a syllable has one tone, typing another one replaces it
+            for other_ch in self.buffer.iter_mut() {
+                *other_ch = util::remove_accents(*other_ch);
+            }
+            let steps = self.replace_char_at(index, replace_ch, true);
+            self.buffer[index] = replace_ch;
+            return steps;
         }
         vec![]
     }
 
-    fn handle_normal_char(&mut self, ch: char) -> Vec<Action> {
-        match ch {
-            TRIGGER_CIRCUMFLEX => self.add_diacritic(vec![
-                DiacriticMatch {
-                    ch: 'a',
-                    pair_with: vec!['u', 'n', 'm', 'p', 't', 'c', 'y'],
-                    replace_with: ('â', 'Â'),
-                },
-                DiacriticMatch {
-                    ch: 'e',
-                    pair_with: vec!['u', 'n', 'm', 'p', 't', 'c', 'y'],
-                    replace_with: ('ê', 'Ê'),
-                },
-                DiacriticMatch {
-                    ch: 'o',
-                    pair_with: vec!['i', 'n', 'm', 'p', 't', 'c', 'y'],
+    /// Take the horn off a ư left before an ô, a circumflex replacing the
+    /// horn of ươ turns the whole nucleus into uô (ươ + 6 -> uô)
+    ///
+    /// Diacritics on the same vowel replace each other instead of stacking,
+    /// o67 gives ơ and o76 gives ô.
+    fn unhorn_before_circumflex(&mut self) -> Vec<Action> {
+        let index = (1..self.buffer.len()).find(|&i| {
+            "ưƯ".contains(util::remove_accents(self.buffer[i - 1]))
+                && "ôÔ".contains(util::remove_accents(self.buffer[i]))
+        });
+        let index = match index {
+            Some(i) => i - 1,
+            None => return Vec::new(),
+        };
+        let ch = self.buffer[index];
+        let u = if util::remove_accents(ch) == 'Ư' { 'U' } else { 'u' };
+        let replace_ch = keep_tone(ch, u);
+        let steps = self.replace_char_at(index, replace_ch, true);
+        self.buffer[index] = replace_ch;
+        steps
+    }
+
+    /// Move the tone to the vowel that takes it now that a diacritic
+    /// changed the nucleus (toán + 6 -> toấn, hóa + 6 -> hoấ)
+    ///
+    /// The tone stays put when the vowel that should take it is after the
+    /// caret.
+    fn replace_tone(&mut self) -> Vec<Action> {
+        let from = self.buffer
+            .iter()
+            .position(|&ch| util::remove_accents(ch) != ch);
+        let (from, to) = match (from, self.get_vowel_for_accent()) {
+            (Some(from), Some((_, to))) if from != to => (from, to),
+            _ => return Vec::new(),
+        };
+        let toned_ch = self.buffer[from];
+        self.buffer[from] = util::remove_accents(toned_ch);
+        let replace_ch = keep_tone(toned_ch, self.buffer[to]);
+        let steps = self.replace_char_at(from.min(to), self.buffer[from.min(to)], true);
+        self.buffer[to] = replace_ch;
+        steps
+    }
+
+    /// Cross the d starting the syllable, or uncross it (d9 -> đ, đ9 -> d)
+    ///
+    /// đ is only ever the onset so no other d takes it, dd9 gives đd and
+    /// ad9 stays as typed. VNI has no dd -> đ, a doubled d stays dd.
+    fn cross_d(&mut self) -> Vec<Action> {
+        let replace_ch = match self.buffer.first() {
+            Some('d') => 'đ',
+            Some('D') => 'Đ',
+            Some('đ') => 'd',
+            Some('Đ') => 'D',
+            _ => return Vec::new(),
+        };
+        let steps = self.replace_char_at(0, replace_ch, true);
+        self.buffer[0] = replace_ch;
+        steps
+    }
+
+    /// Take the tone off every vowel, nothing to do without one
+    ///
+    /// Only the tone goes, the i or y typed stays (mỹ -> my).
+    fn remove_tone(&mut self) -> Vec<Action> {
+        let index = match self.buffer
+            .iter()
+            .position(|&ch| util::remove_accents(ch) != ch) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        for ch in self.buffer.iter_mut() {
+            *ch = util::remove_accents(*ch);
+        }
+        self.replace_char_at(index, self.buffer[index], true)
+    }
+
+    fn is_trigger(&self, ch: char) -> bool {
+        self.trigger_mark(ch).is_some()
+    }
+
+    /// The mark `ch` triggers, if it's a trigger
+    fn trigger_mark(&self, ch: char) -> Option<Mark> {
+        let config = &self.config;
+        [
+            (config.trigger_acute, Mark::Acute),
+            (config.trigger_grave, Mark::Grave),
+            (config.trigger_hook_above, Mark::HookAbove),
+            (config.trigger_tilde, Mark::Tilde),
+            (config.trigger_dot, Mark::Dot),
+            (config.trigger_circumflex, Mark::Circumflex),
+            (config.trigger_horn, Mark::Horn),
+            (config.trigger_breve, Mark::Breve),
+            (config.trigger_crossed_d, Mark::CrossedD),
+            (config.trigger_remove_tone, Mark::RemoveTone),
+        ]
+            .iter()
+            .find(|&&(trigger, _)| {
+                // letter triggers work with shift or caps lock too
+                trigger == ch
+                    || trigger.is_ascii_alphabetic() && trigger.eq_ignore_ascii_case(&ch)
+            })
+            .map(|&(_, mark)| mark)
+            .or_else(|| {
+                (config.w_as_horn && ch.eq_ignore_ascii_case(&'w')).then_some(Mark::Horn)
+            })
+    }
+
+    /// Whether `ch` is a punctuation tone trigger typed where it can't put
+    /// a tone, after a consonant or outside a syllable (don't, it's)
+    fn is_stray_punctuation_tone(&self, ch: char) -> bool {
+        let after_vowel = self.buffer.last().map_or(false, |&last| {
+            "aeiouy".contains(util::clean_char(last).to_ascii_lowercase())
+        });
+        self.is_punctuation_tone(ch)
+            && !(after_vowel && util::is_valid_syllable(&self.buffer))
+    }
+
+    fn is_punctuation_tone(&self, ch: char) -> bool {
+        self.config.punctuation_tone_after_vowel
+            && ch.is_ascii_punctuation()
+            && self.is_tone_trigger(ch)
+    }
+
+    /// Whether `ch` ends the syllable, a separator that is also a
+    /// punctuation tone trigger only does where it can't put the tone
+    fn is_separator(&self, ch: char) -> bool {
+        self.config.syllable_separators.contains(&ch)
+            && (!self.is_punctuation_tone(ch) || self.is_stray_punctuation_tone(ch))
+    }
+
+    /// Rewrite a word initial iê to yê (iên -> yên, iêu -> yêu), a
+    /// syllable with an onset keeps its i (tiên)
+    ///
+    /// Runs after the trigger applied, so it sees the syllable as that left
+    /// it whichever the trigger was.
+    fn normalize_initial_ie(&mut self) -> Vec<Action> {
+        let starts_with_ie = self.buffer.len() > 1
+            && (self.buffer[0] == 'i' || self.buffer[0] == 'I')
+            && "êÊ".contains(util::remove_accents(self.buffer[1]));
+        if !starts_with_ie {
+            return Vec::new();
+        }
+        let y = if self.buffer[0] == 'I' { 'Y' } else { 'y' };
+        let steps = self.replace_char_at(0, y, false);
+        self.buffer[0] = y;
+        steps
+    }
+
+    /// Index of the first o of the first oo in the buffer, tones allowed
+    fn double_o_index(&self) -> Option<usize> {
+        let is_plain_o = |ch: char| util::remove_accents(ch).eq_ignore_ascii_case(&'o');
+        (1..self.buffer.len())
+            .find(|&i| is_plain_o(self.buffer[i - 1]) && is_plain_o(self.buffer[i]))
+            .map(|i| i - 1)
+    }
+
+    /// Merge the first oo of the buffer into ô, keeping a tone either o
+    /// carries (oó -> ố)
+    fn collapse_double_o(&mut self) -> Vec<Action> {
+        let index = match self.double_o_index() {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let first = self.buffer[index];
+        let second = self.buffer.remove(index + 1);
+        let o_hat = if first.is_uppercase() { 'Ô' } else { 'ô' };
+        let toned = if util::remove_accents(first) != first { first } else { second };
+        let replace_char = keep_tone(toned, o_hat);
+        let steps = self.replace_char_at(index, replace_char, true);
+        self.buffer[index] = replace_char;
+        steps
+    }
+
+    fn handle_normal_char(&mut self, ch: char) -> Vec<Action> {
+        let mark = self.trigger_mark(ch);
+        let is_disabled = mark
+            .map_or(false, |mark| self.config.disabled_marks.contains(&mark));
+        let is_foreign = self.buffer.iter().any(|ch| {
+            self.config.foreign_letters
+                .iter()
+                .any(|letter| letter.eq_ignore_ascii_case(ch))
+        });
+        if is_disabled || is_foreign || self.is_stray_punctuation_tone(ch) {
+            return Vec::new();
+        }
+        if self.config.smart && self.is_trigger(ch) {
+            let is_valid = util::is_valid_syllable(&self.buffer)
+                && mark
+                    .and_then(Mark::tone)
+                    .map_or(true, |tone| util::allows_tone(&self.buffer, tone));
+            if !is_valid {
+                return Vec::new();
+            }
+        }
+        let typed = self.buffer.iter().chain(Some(&ch)).collect::<String>();
+        let old_buffer = self.is_observed().then(|| self.buffer.clone());
+        let mut steps = self.apply_trigger(ch);
+        if steps.is_empty() && self.config.pending_tone && self.is_tone_trigger(ch) {
+            self.pending_tone = Some(ch);
+        }
+        let is_diacritic = matches!(
+            mark,
+            Some(Mark::Circumflex) | Some(Mark::Horn) | Some(Mark::Breve)
+        );
+        if !steps.is_empty() && mark == Some(Mark::Circumflex) {
+            steps.extend(self.unhorn_before_circumflex());
+        }
+        if !steps.is_empty() && is_diacritic {
+            steps.extend(self.replace_tone());
+        }
+        if !steps.is_empty() && self.config.initial_ie_as_ye {
+            steps.extend(self.normalize_initial_ie());
+        }
+        if steps.is_empty() {
+            return steps;
+        }
+        let removed_tone = mark.and_then(Mark::tone).is_some()
+            && self.buffer.iter().all(|&ch| util::remove_accents(ch) == ch);
+        if removed_tone && ch.is_alphabetic() {
+            // a letter taking its own tone off stays typed, as in Telex
+            // (ass gives as) where a digit doesn't (a11 gives a)
+            self.buffer.push(ch);
+        }
+        if let Some(old_buffer) = old_buffer {
+            self.notify(&old_buffer, ch);
+        }
+        // the steps fix the document one edit at a time, diffing what got
+        // typed against the result touches each char at most once
+        diff_actions(&typed, &self.buffer.iter().collect::<String>())
+    }
+
+    fn apply_trigger(&mut self, ch: char) -> Vec<Action> {
+        let mark = self.trigger_mark(ch);
+        if mark == Some(Mark::Circumflex) && self.double_o_index().is_some() {
+            // oo only shows up in loanwords (xoong, boong, goòng) which
+            // never take a circumflex, without collapsing it the trigger is
+            // typed as is rather than give oô
+            if self.config.collapse_double_o {
+                return self.collapse_double_o();
+            }
+            return Vec::new();
+        }
+        if mark == Some(Mark::Horn) && self.double_o_index().is_some() {
+            // nor a horn, boong7 stays boong7 rather than give boơng
+            return Vec::new();
+        }
+        match mark {
+            Some(Mark::Circumflex) => self.add_diacritic(vec![
+                // ây exists (mây, dây) so y pairs with a here, ăy doesn't
+                // so y is left out of the breve pairs below
+                DiacriticMatch {
+                    ch: 'a',
+                    pair_with: PairSet::new(&['u', 'n', 'm', 'p', 't', 'c', 'y']),
+                    replace_with: ('â', 'Â'),
+                },
+                DiacriticMatch {
+                    ch: 'e',
+                    pair_with: PairSet::new(&['u', 'n', 'm', 'p', 't', 'c', 'y']),
+                    replace_with: ('ê', 'Ê'),
+                },
+                DiacriticMatch {
+                    ch: 'o',
+                    pair_with: PairSet::new(&['i', 'n', 'm', 'p', 't', 'c', 'y']),
                     replace_with: ('ô', 'Ô'),
                 }
             ]),
-            TRIGGER_HORN => self.add_diacritic(vec![
+            Some(Mark::Horn) => self.add_diacritic(vec![
+                // u pairs with u for ưu (cừu, hưu), uư doesn't exist
                 DiacriticMatch {
                     ch: 'u',
-                    pair_with: vec!['o', 'i', 'n', 'm', 'a', 'p', 't', 'c'],
+                    pair_with: PairSet::new(&['o', 'i', 'n', 'm', 'a', 'p', 't', 'c', 'u']),
                     replace_with: ('ư', 'Ư'),
                 },
                 DiacriticMatch {
                     ch: 'o',
-                    pair_with: vec!['i', 'n', 'm', 'p', 't', 'c', 'y'],
+                    pair_with: PairSet::new(&['i', 'n', 'm', 'p', 't', 'c', 'y']),
                     replace_with: ('ơ', 'Ơ'),
                 }
             ]),
-            TRIGGER_BREVE => self.add_diacritic(vec![
+            Some(Mark::Breve) => self.add_diacritic(vec![
+                // only a takes a breve, without one the trigger is typed
+                // literally and leaves ư and ơ alone (ươ8 stays ươ8), ă
+                // takes all five tones like any vowel (ăn1 gives ắn)
                 DiacriticMatch {
                     ch: 'a',
-                    pair_with: vec!['p', 'n', 'm', 't', 'c'],
+                    pair_with: PairSet::new(&['p', 'n', 'm', 't', 'c']),
                     replace_with: ('ă', 'Ă'),
                 }
             ]),
-            TRIGGER_CROSSED_D => self.add_diacritic(vec![
-                DiacriticMatch {
-                    ch: 'd',
-                    pair_with: vec!['a', 'c', 'e', 'i', 'm', 'n', 'o', 'p', 't', 'u', 'y'],
-                    replace_with: ('đ', 'Đ'),
-                }
-            ]),
-            TRIGGER_ACUTE => self.add_accent(character_map::ACUTE_MAP),
-            TRIGGER_GRAVE => self.add_accent(character_map::GRAVE_MAP),
-            TRIGGER_HOOK_ABOVE => self.add_accent(character_map::HOOK_ABOVE_MAP),
-            TRIGGER_TILDE => self.add_accent(character_map::TILDE_MAP),
-            TRIGGER_DOT => self.add_accent(character_map::DOT_MAP),
-            _ => Vec::new()
+            Some(Mark::CrossedD) => self.cross_d(),
+            Some(Mark::RemoveTone) => self.remove_tone(),
+            Some(Mark::Acute) => self.add_accent(self.tone_map(Tone::Acute)),
+            Some(Mark::Grave) => self.add_accent(self.tone_map(Tone::Grave)),
+            Some(Mark::HookAbove) => self.add_accent(self.tone_map(Tone::HookAbove)),
+            Some(Mark::Tilde) => self.add_accent(self.tone_map(Tone::Tilde)),
+            Some(Mark::Dot) => self.add_accent(self.tone_map(Tone::Dot)),
+            None => Vec::new()
+        }
+    }
+
+    /// Put a tone typed before any vowel on the vowel `ch` typed right
+    /// after it, any other char leaves the trigger typed as is
+    fn apply_pending_tone(&mut self, trigger: char, ch: char) -> Vec<Action> {
+        let is_vowel = "aeiouy".contains(util::clean_char(ch).to_ascii_lowercase());
+        if !is_vowel || self.buffer.last() != Some(&trigger) {
+            return self.handle_normal_char(ch);
+        }
+        let typed = self.buffer.iter().chain(Some(&ch)).collect::<String>();
+        self.buffer.pop();
+        self.buffer.push(ch);
+        let old_buffer = self.is_observed().then(|| self.buffer.clone());
+        if self.apply_trigger(trigger).is_empty() {
+            self.buffer.pop();
+            self.buffer.push(trigger);
+            return self.handle_normal_char(ch);
+        }
+        if let Some(old_buffer) = old_buffer {
+            self.notify(&old_buffer, trigger);
+        }
+        diff_actions(&typed, &self.buffer.iter().collect::<String>())
+    }
+
+    /// Whether the vowel `ch` starts a new syllable, the buffer being a
+    /// whole syllable closed by a coda already
+    ///
+    /// Words typed without a space in between (anhem1 gives anhém) then get
+    /// their tone on the syllable being typed. The coda stays with the
+    /// syllable it closes and the vowel starts the next one without an
+    /// onset, only the document keeps the syllable before it.
+    fn starts_new_syllable(&self, ch: char) -> bool {
+        let is_vowel = "aeiouy".contains(util::clean_char(ch).to_ascii_lowercase());
+        // only a coda letter at the end is worth splitting the buffer for,
+        // this runs on every letter typed
+        let after_coda_letter = matches!(
+            self.buffer.last(),
+            Some(last) if "cghmnpt".contains(last.to_ascii_lowercase())
+        );
+        is_vowel
+            && after_coda_letter
+            && !self.is_trigger(ch)
+            && util::ends_in_coda(&self.buffer)
+    }
+
+    fn is_observed(&self) -> bool {
+        self.observer.is_some() || self.explained.is_some()
+    }
+
+    /// Tell the observer about every char `trigger` changed, `old` being
+    /// the buffer before it
+    fn notify(&mut self, old: &[char], trigger: char) {
+        // collapsing oo drops a char, the chars after the merged one are
+        // compared with the ones they followed
+        let shift = old.len().saturating_sub(self.buffer.len());
+        let first_change = old
+            .iter()
+            .zip(self.buffer.iter())
+            .position(|(old_ch, new_ch)| old_ch != new_ch);
+        for (index, &new) in self.buffer.iter().enumerate() {
+            let old_index = match first_change {
+                Some(first_change) if index > first_change => index + shift,
+                _ => index,
+            };
+            let old = match old.get(old_index) {
+                Some(&old) if old != new => old,
+                _ => continue,
+            };
+            let transformation = Transformation { index, old, new, trigger };
+            if let Some(observer) = self.observer.as_mut() {
+                observer(&transformation);
+            }
+            if let Some(explained) = self.explained.as_mut() {
+                explained.push(transformation);
+            }
+        }
+    }
+
+    /// Replace the word being committed by `ch` if it's in the autocorrect
+    /// table, the whitespace has already been typed so it's typed again
+    /// after the replacement
+    fn autocorrect(&mut self, ch: char) -> Vec<Action> {
+        let word = self.buffer.iter().collect::<String>();
+        let replacement = match self.config.autocorrect.get(&word) {
+            Some(replacement) => replacement.clone(),
+            None => return Vec::new()
+        };
+        let mut steps = vec![Action::Backspace(self.buffer.len() + 1)];
+        steps.extend(replacement.chars().map(Action::Insert));
+        steps.push(Action::Insert(ch));
+        self.buffer = replacement.chars().collect();
+        steps
+    }
+
+    /// Take the marks off the syllable being committed if the dictionary
+    /// doesn't have it, `typed` being the char that committed it, already
+    /// in the document and typed again after the letters
+    fn revert_unknown_word(&mut self, typed: Option<char>) -> Vec<Action> {
+        if !self.config.revert_if_not_in_dict || !self.is_composing() {
+            return Vec::new();
+        }
+        let word = self.buffer.iter().collect::<String>().to_lowercase();
+        if self.config.dictionary.contains(&word) {
+            return Vec::new();
+        }
+        let mut steps = self.undo_all();
+        if let Some(ch) = typed {
+            match steps.first_mut() {
+                Some(Action::Backspace(amount)) => *amount += 1,
+                _ => steps.insert(0, Action::Backspace(1)),
+            }
+            steps.push(Action::Insert(ch));
+        }
+        steps
+    }
+
+    /// Keep the syllable around after a whitespace if the soft commit
+    /// window is enabled
+    fn commit_softly(&mut self, ch: char, soft_commit: Option<SoftCommit>)
+                     -> Option<SoftCommit> {
+        if self.config.soft_commit_window == 0 {
+            return None;
+        }
+        if !self.buffer.is_empty() {
+            return Some(SoftCommit {
+                buffer: self.buffer.clone(),
+                trailing: vec![ch],
+                keys_left: self.config.soft_commit_window,
+            });
+        }
+        soft_commit.map(|mut commit| {
+            commit.trailing.push(ch);
+            commit
+        })
+    }
+
+    /// Apply a trigger to a soft committed syllable
+    ///
+    /// The actions are computed as if the syllable was still in the buffer,
+    /// then the whitespace after it is deleted and typed again around them.
+    fn amend_soft_commit(&mut self, mut commit: SoftCommit, ch: char)
+                         -> Vec<Action> {
+        self.buffer = mem::take(&mut commit.buffer);
+        let mut actions = self.handle_normal_char(ch);
+        if actions.is_empty() {
+            self.buffer.clear();
+            return actions;
+        }
+        if let Some(Action::Backspace(amount)) = actions.first_mut() {
+            *amount += commit.trailing.len();
+        }
+        for &whitespace in &commit.trailing {
+            actions.push(Action::Insert(whitespace));
+        }
+        commit.buffer = mem::take(&mut self.buffer);
+        self.soft_commit = Some(commit);
+        actions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Whether the buffer holds a character with a tone or diacritic
+    ///
+    /// A buffer of plain letters isn't composing since committing it
+    /// changes nothing, so a host can let Enter through as is.
+    pub fn is_composing(&self) -> bool {
+        self.buffer.iter().any(|&ch| util::clean_char(ch) != ch)
+    }
+
+    /// The word being composed and the char index of the caret in it, for
+    /// hosts rendering a preedit (tieng61 gives `("tiếng", 5)`)
+    ///
+    /// The caret is at the end unless `set_context` gave letters after it,
+    /// which are part of the word.
+    pub fn preview(&self) -> (String, usize) {
+        let word = self.buffer
+            .iter()
+            .chain(self.after_context.iter())
+            .collect::<String>();
+        (word, self.buffer.len())
+    }
+
+    /// Replace the syllable being composed with `text` without touching
+    /// the document, for when the host already shows `text`
+    ///
+    /// Only what follows the last char that isn't ascii or vietnamese
+    /// (emoji, CJK) is composed, the rest stays in the document as is.
+    pub fn set_buffer(&mut self, text: &str) {
+        let mut buffer = text
+            .chars()
+            .rev()
+            .take_while(|&ch| util::is_vietnamese_char(ch))
+            .collect::<Vec<char>>();
+        buffer.reverse();
+        self.buffer = buffer;
+        self.after_context.clear();
+        self.soft_commit = None;
+    }
+
+    /// Replace the syllable being composed with `text` and return the
+    /// actions making the document show it instead of the old syllable
+    pub fn reset_to(&mut self, text: &str) -> Vec<Action> {
+        let old = self.buffer.iter().collect::<String>();
+        let actions = diff_actions(&old, text);
+        self.set_buffer(text);
+        actions
+    }
+
+    /// Turn the letter at `base_index` of the syllable into `variant`, one
+    /// of its `variants_of`, and return the actions making the document
+    /// show it, for on-screen keyboards with an accent picker (picking ế
+    /// for the e of `ten` gives `tến`)
+    ///
+    /// A toned variant takes the tone off the other letters since a
+    /// syllable has one. Nothing happens for an index past the syllable or
+    /// a variant the letter doesn't have.
+    pub fn apply_variant(&mut self, base_index: usize, variant: char) -> Vec<Action> {
+        let is_variant = self.buffer
+            .get(base_index)
+            .map_or(false, |&ch| util::variants_of(ch).contains(&variant));
+        if !is_variant {
+            return Vec::new();
+        }
+        let old = self.buffer.iter().collect::<String>();
+        let old_buffer = self.is_observed().then(|| self.buffer.clone());
+        if util::decompose(variant).2.is_some() {
+            for ch in self.buffer.iter_mut() {
+                *ch = util::remove_accents(*ch);
+            }
+        }
+        self.buffer[base_index] = variant;
+        if let Some(old_buffer) = old_buffer {
+            self.notify(&old_buffer, variant);
+        }
+        diff_actions(&old, &self.buffer.iter().collect::<String>())
+    }
+
+    /// Take every tone and diacritic off the syllable, leaving the letters
+    /// as typed without their triggers (tiếng gives tieng, đi gives di),
+    /// and return the actions making the document show them
+    ///
+    /// The letters stay in the buffer and can be composed again.
+    pub fn undo_all(&mut self) -> Vec<Action> {
+        let old = self.buffer.iter().collect::<String>();
+        for ch in self.buffer.iter_mut() {
+            *ch = util::clean_char(*ch);
+        }
+        diff_actions(&old, &self.buffer.iter().collect::<String>())
+    }
+
+    pub fn snapshot(&self) -> VniSnapshot {
+        VniSnapshot {
+            buffer: self.buffer.clone(),
+            after_context: self.after_context.clone(),
+            soft_commit: self.soft_commit.clone(),
+            placed_style: self.placed_style,
+            pending_tone: self.pending_tone,
         }
     }
 
+    /// Go back to composing the syllable of `snapshot`, the document is
+    /// expected to show it again
+    pub fn restore(&mut self, snapshot: VniSnapshot) {
+        self.buffer = snapshot.buffer;
+        self.after_context = snapshot.after_context;
+        self.soft_commit = snapshot.soft_commit;
+        self.placed_style = snapshot.placed_style;
+        self.pending_tone = snapshot.pending_tone;
+    }
+
+    /// Tell the engine about the text around the caret before typing in
+    /// the middle of a word
+    ///
+    /// The letters right before the caret become the buffer and can be
+    /// composed further, composed ones included (tiế| + 2 gives tiề). The
+    /// letters right after it are only read when picking the vowel for a
+    /// tone (`hoa|n` gets `hoán` even with the classic style) and are never
+    /// edited, a tone that belongs on one of them is typed literally. The
+    /// context is dropped with the buffer.
+    pub fn set_context(&mut self, before: &str, after: &str) {
+        // letters of other scripts (CJK) end the word like punctuation
+        let is_letter = |ch: char| ch.is_alphabetic() && util::is_vietnamese_char(ch);
+        let mut buffer = before
+            .chars()
+            .rev()
+            .take_while(|&ch| is_letter(ch))
+            .collect::<Vec<char>>();
+        buffer.reverse();
+        self.buffer = buffer;
+        self.after_context = after
+            .chars()
+            .take_while(|&ch| is_letter(ch))
+            .collect();
+        self.soft_commit = None;
+    }
+
+    /// Call `f` with every change a trigger makes to the syllable, tones,
+    /// diacritics and taking them back off, for debugging tools showing what
+    /// the engine decided
+    ///
+    /// Replaces the previous observer. Without one nothing is recorded.
+    pub fn set_observer(&mut self, f: Observer) {
+        self.observer = Some(f);
+    }
+
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Bring the syllable back in line with the document after something
+    /// else edited it (the OS autocorrect, the user fixing a vowel by hand),
+    /// `actual_text` being what the document has before the caret
+    ///
+    /// Nothing is typed, the letters ending `actual_text` become the
+    /// syllable like with `set_context` so the next trigger backspaces the
+    /// right amount. The letters after the caret are kept.
+    pub fn resync(&mut self, actual_text: &str) {
+        let after = mem::take(&mut self.after_context)
+            .into_iter()
+            .collect::<String>();
+        self.set_context(actual_text, &after);
+        self.placed_style = None;
+        self.pending_tone = None;
+    }
+
+    /// The last key presses as typed, oldest first, for attaching to bug
+    /// reports. Backspace shows up as ⌫ and the arrows as ← → ↑ ↓.
+    ///
+    /// Always empty unless `VniConfig::key_log_size` is set.
+    pub fn recent_keys(&self) -> String {
+        self.key_log.iter().collect()
+    }
+
+    pub fn clear_recent_keys(&mut self) {
+        self.key_log.clear();
+    }
+
+    fn log_key(&mut self, key: &PhysicKey, ch: char) {
+        if self.config.key_log_size == 0 {
+            return;
+        }
+        let logged_ch = match key.keycode {
+            keycodes::KEY_BACKSPACE => '⌫',
+            keycodes::KEY_LEFT => '←',
+            keycodes::KEY_RIGHT => '→',
+            keycodes::KEY_UP => '↑',
+            keycodes::KEY_DOWN => '↓',
+            _ if ch != '\0' => ch,
+            _ => return
+        };
+        while self.key_log.len() >= self.config.key_log_size {
+            self.key_log.pop_front();
+        }
+        self.key_log.push_back(logged_ch);
+    }
+
+    /// Compose `key`, which the host has already typed into the document,
+    /// and return the actions fixing the document up afterward
+    ///
+    /// The host types every key itself and never again: no actions means
+    /// the key stays in the document as typed (a letter, a trigger with
+    /// nothing to compose), otherwise applying the actions right away, the
+    /// key included in what they backspace over, gives the composed text
+    /// (tieng6 gives a backspace of 4 then ê n g, the 6 being deleted).
+    /// The commit key is the exception, the host swallows it so the actions
+    /// it gives (taking the marks off an unknown word) stop short of it.
+    ///
+    /// A mark keeps the case of the letter it goes on, a capital vowel
+    /// starting the word included (Ong6 gives Ông, Anh1 gives Ánh). Case
+    /// is never assumed uniform across the syllable, a shift held or
+    /// released mid-word keeps each letter as typed (tIeng61 gives tIếng).
+    ///
+    /// A key typing no char (`\0`, a function key) only goes through with
+    /// no actions, unless it's an arrow or the commit key.
+    ///
+    /// Backspace is never consumed: the host deletes the char before the
+    /// caret as usual and the engine drops the same char from the syllable,
+    /// a composed char being a single one (ường + backspace leaves ườn), so
+    /// backspace always gives no actions. Backspacing the whole syllable
+    /// away leaves nothing to compose, a trigger typed next stays literal
+    /// (a1, backspace, 1 gives 1).
+    #[must_use = "apply the returned actions to the document"]
     pub fn handle_key(&mut self, key: PhysicKey) -> Vec<Action> {
         let mut ch: char = key.clone().into();
         let mut actions: Vec<Action> = Vec::new();
+        // keys typing nothing (F1, media keys, modifiers) neither compose
+        // nor end the syllable, the engine is left as it was
+        let types_nothing = ch == '\0'
+            && !key.is_arrow()
+            && !key.is_backspace()
+            && self.config.commit_key != Some(key.keycode);
+        if types_nothing {
+            return actions;
+        }
         if let KeyState::KeyPress = key.state {
+            let mut soft_commit = self.soft_commit.take().and_then(|mut commit| {
+                if commit.keys_left == 0 {
+                    return None;
+                }
+                commit.keys_left -= 1;
+                Some(commit)
+            });
+            let placed_style = self.placed_style.take();
+            let pending_tone = self.pending_tone.take();
             let mut clear_buffer = false;
-            if key.is_arrow() || key.is_whitespace() {
+            // typed into the syllable rather than passing through or ending it
+            let mut is_typed_in = false;
+            if self.config.commit_key == Some(key.keycode) {
+                clear_buffer = true;
+                actions = self.revert_unknown_word(None);
+                if let Some(style) = placement_style(&self.buffer) {
+                    self.vote_style(style);
+                }
+            } else if key.is_arrow() {
+                clear_buffer = true;
+            } else if key.keycode == keycodes::KEY_ENTER
+                && self.config.enter == EnterBehavior::Commit
+                && self.is_composing() {
                 clear_buffer = true;
+                actions = vec![Action::Backspace(1)];
+                if let Some(style) = placement_style(&self.buffer) {
+                    self.vote_style(style);
+                }
+            } else if self.config.hidden_separators.contains(&key.to_char()) {
+                ch = key.to_char();
+                clear_buffer = true;
+                actions = vec![Action::Backspace(1)];
+                if let Some(style) = placement_style(&self.buffer) {
+                    self.vote_style(style);
+                }
+            } else if self.is_separator(key.to_char()) {
+                ch = key.to_char();
+                clear_buffer = true;
+                actions = self.revert_unknown_word(Some(ch));
+                if let Some(style) = placement_style(&self.buffer) {
+                    self.vote_style(style);
+                }
+            } else if key.is_whitespace() {
+                clear_buffer = true;
+                actions = self.autocorrect(ch);
+                if actions.is_empty() {
+                    actions = self.revert_unknown_word(Some(ch));
+                }
+                if let Some(style) = placement_style(&self.buffer) {
+                    self.vote_style(style);
+                }
+                self.soft_commit = self.commit_softly(ch, soft_commit);
             } else if key.is_backspace() {
-                self.buffer.pop();
+                match placed_style {
+                    Some(ToneStyle::Modern) => self.vote_style(ToneStyle::Classic),
+                    Some(ToneStyle::Classic) => self.vote_style(ToneStyle::Modern),
+                    Some(ToneStyle::LastVowel) | None => {}
+                }
+                match soft_commit {
+                    Some(mut commit) if self.buffer.is_empty() => {
+                        commit.trailing.pop();
+                        if commit.trailing.is_empty() {
+                            self.buffer = commit.buffer;
+                        } else {
+                            self.soft_commit = Some(commit);
+                        }
+                    }
+                    _ => {
+                        self.buffer.pop();
+                    }
+                }
+            } else if key.is_keypad() && !self.config.keypad_triggers {
+                // typed as part of the syllable without composing anything
+                ch = key.to_char();
+                is_typed_in = true;
             } else {
-                ch = match key.cap {
-                    Some(_) => ch.to_ascii_uppercase(),
-                    None => ch
+                ch = key.to_char();
+                is_typed_in = true;
+                if self.starts_new_syllable(ch) {
+                    self.buffer.clear();
+                    soft_commit = None;
+                }
+                actions = match soft_commit {
+                    Some(commit) if self.buffer.is_empty() => {
+                        self.amend_soft_commit(commit, ch)
+                    }
+                    _ => match pending_tone {
+                        Some(trigger) => self.apply_pending_tone(trigger, ch),
+                        None => self.handle_normal_char(ch)
+                    }
                 };
-                actions = self.handle_normal_char(ch);
+                if !actions.is_empty() && self.is_tone_trigger(ch) {
+                    self.placed_style = placement_style(&self.buffer);
+                }
             }
+            self.log_key(&key, ch);
             if clear_buffer {
                 self.buffer.clear();
+                self.after_context.clear();
             } else if ch != '\0' && actions.is_empty() {
+                self.make_room();
                 self.buffer.push(ch);
             }
+            if is_typed_in && self.config.output_mode == OutputMode::ReplaceWord {
+                actions = self.replace_word(actions);
+            }
+        }
+        actions
+    }
+
+    /// Turn the actions of a key typed into the syllable into a single one
+    /// replacing the syllable, `actions` being kept when they also edit
+    /// what's before it (a soft committed syllable)
+    fn replace_word(&self, actions: Vec<Action>) -> Vec<Action> {
+        let word = self.buffer.iter().collect::<String>();
+        let inserted = actions
+            .iter()
+            .filter_map(|action| match *action {
+                Action::Insert(ch) => Some(ch),
+                _ => None
+            })
+            .collect::<String>();
+        let delta: isize = actions.iter().map(Action::char_delta).sum();
+        let replaced_len = self.buffer.len() as isize - delta;
+        if self.buffer.is_empty() || !word.ends_with(&inserted) || replaced_len < 0 {
+            return actions;
+        }
+        vec![Action::ReplaceWord(replaced_len as usize, word)]
+    }
+
+    /// Make room for one more char when the buffer is full, following the
+    /// overflow policy
+    ///
+    /// The host has already typed every char, so neither policy changes the
+    /// document, only which chars later triggers can edit.
+    fn make_room(&mut self) {
+        let max_len = self.config.max_buffer_len;
+        if max_len == 0 || self.buffer.len() < max_len {
+            return;
+        }
+        match self.config.overflow_policy {
+            OverflowPolicy::DropOldest => {
+                let overflow = self.buffer.len() + 1 - max_len;
+                self.buffer.drain(..overflow);
+            }
+            OverflowPolicy::CommitAndReset => {
+                self.buffer.clear();
+                self.after_context.clear();
+            }
+        }
+    }
+
+    /// Handle a key like `handle_key`, for hosts that get the key before
+    /// the app does and decide whether it goes through
+    ///
+    /// A key that composes is swallowed and the actions assume it never
+    /// reached the document (a + 1 gives a backspace and á with the 1
+    /// swallowed). A letter or a trigger that stays literal goes through
+    /// with no actions, except with `OutputMode::ReplaceWord` where every
+    /// key typed into the syllable is swallowed and replaced along with it.
+    /// The commit key is always swallowed, its actions apply as they are.
+    pub fn handle_key_response(&mut self, key: PhysicKey) -> KeyResponse {
+        let is_commit_key = self.config.commit_key == Some(key.keycode);
+        let mut actions = self.handle_key(key);
+        if is_commit_key {
+            return KeyResponse { actions, swallow_original: true };
+        }
+        // the first backspace of the actions of a key always covers the key
+        // itself, which the host hasn't typed yet here
+        let swallow_original = match actions.first_mut() {
+            Some(Action::Backspace(amount)) | Some(Action::ReplaceWord(amount, _)) => {
+                *amount -= 1;
+                true
+            }
+            _ => false,
+        };
+        if actions.first() == Some(&Action::Backspace(0)) {
+            actions.remove(0);
+        }
+        KeyResponse { actions, swallow_original }
+    }
+
+    /// Handle a key like `handle_key` and also describe what it changed,
+    /// one line per char, for apps teaching how the marks get placed
+    ///
+    /// The 1 of hoan1 gives "1: put the acute on a at 2 (a -> á)", a key that
+    /// changes nothing gives "typed as is".
+    pub fn handle_key_explained(&mut self, key: PhysicKey) -> (Vec<Action>, String) {
+        self.explained = Some(Vec::new());
+        let actions = self.handle_key(key);
+        let explained = self.explained.take().unwrap_or_default();
+        if explained.is_empty() {
+            return (actions, "typed as is".to_string());
+        }
+        let lines = explained
+            .iter()
+            .map(explain)
+            .collect::<Vec<String>>();
+        (actions, lines.join("\n"))
+    }
+
+    /// Whether `handle_key` would take `key` into the syllable, a letter or
+    /// the trigger of a mark that isn't disabled
+    ///
+    /// Releases, keys typing nothing (modifiers), whitespace, separators,
+    /// arrows, backspace and the commit key only pass through or end the
+    /// syllable, so they give false. The host still types the key either way.
+    pub fn would_consume(&self, key: &PhysicKey) -> bool {
+        let ch = key.to_char();
+        let is_composing_key = key.state == KeyState::KeyPress
+            && self.config.commit_key != Some(key.keycode)
+            && !key.is_arrow()
+            && !key.is_whitespace()
+            && !key.is_backspace()
+            && !self.is_separator(ch)
+            && !self.config.hidden_separators.contains(&ch);
+        let is_active_trigger = (!key.is_keypad() || self.config.keypad_triggers)
+            && self
+                .trigger_mark(ch)
+                .map_or(false, |mark| !self.config.disabled_marks.contains(&mark));
+        is_composing_key && (ch.is_alphabetic() || is_active_trigger)
+    }
 
-            println!("{:?}", self.buffer);
+    /// The tone style the user seems to prefer, an engine never switches
+    /// style on its own
+    ///
+    /// Only open oa, oe, oy and uy syllables tell the styles apart. Committing
+    /// one with whitespace counts for the style its tone follows, pressing
+    /// backspace right after the engine put a tone on one counts for the
+    /// other style since the user is taking that placement back. Ties keep
+    /// the configured style.
+    pub fn suggested_style(&self) -> ToneStyle {
+        if self.modern_votes > self.classic_votes {
+            ToneStyle::Modern
+        } else if self.classic_votes > self.modern_votes {
+            ToneStyle::Classic
+        } else {
+            self.config.tone_style
+        }
+    }
+
+    fn vote_style(&mut self, style: ToneStyle) {
+        match style {
+            ToneStyle::Modern => self.modern_votes += 1,
+            ToneStyle::Classic => self.classic_votes += 1,
+            ToneStyle::LastVowel => {}
+        }
+    }
+
+    fn is_tone_trigger(&self, ch: char) -> bool {
+        self.trigger_mark(ch).and_then(Mark::tone).is_some()
+    }
+
+    /// Handle a key like `handle_key` but keep the actions queued for
+    /// `next_action`, for hosts that deliver one action per event
+    pub fn queue_key(&mut self, key: PhysicKey) {
+        let actions = self.handle_key(key);
+        self.pending_actions.extend(actions);
+    }
+
+    /// Feed `keys` one by one and return every action they produced, in
+    /// order, for playing recorded input back
+    ///
+    /// The engine ends up as if the keys were fed one at a time. The
+    /// actions of a key only apply right after that key reached the
+    /// document, they can't be applied in one go after typing all the keys.
+    pub fn replay(&mut self, keys: &[PhysicKey]) -> Vec<Action> {
+        keys.iter()
+            .flat_map(|key| self.handle_key(key.clone()))
+            .collect()
+    }
+
+    /// Compose `text` as if each char was typed in order, for hosts handing
+    /// over several chars at once (pre-edit, a short paste)
+    ///
+    /// Unlike `handle_key` the text isn't in the document yet, the actions
+    /// type each char then fix it up, so applying them gives the composed
+    /// text (tieng61 gives tiếng, `xin chao2` gives `xin chào`). A char no
+    /// key types (`.`, emoji) is typed as is and ends the syllable, a
+    /// hidden separator isn't typed at all.
+    pub fn handle_text(&mut self, text: &str) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for ch in text.chars() {
+            if self.config.hidden_separators.contains(&ch) {
+                self.set_buffer("");
+                continue;
+            }
+            actions.push(Action::Insert(ch));
+            match PhysicKey::from_typed_char(ch) {
+                Some(key) => actions.extend(self.handle_key(key)),
+                None => self.set_buffer(""),
+            }
         }
         actions
     }
+
+    /// Take the oldest action queued by `queue_key`
+    pub fn next_action(&mut self) -> Option<Action> {
+        self.pending_actions.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{apply_actions, keycodes, KeyCap};
+    use std::sync::{Arc, Mutex};
+
+    // backspace in the keys given to `type_keys`
+    const BACKSPACE: char = '⌫';
+
+    // a key typing no char, F1 on X11
+    const KEY_F1: u32 = 67;
+
+    // Type `keys` into a document the way a host does, each key then its
+    // actions, and give the document back
+    fn type_keys(vni: &mut Vni, keys: &str) -> String {
+        type_keys_after(vni, "", keys)
+    }
+
+    // Same with `before` already in the document and given to the engine
+    // as the syllable being composed
+    fn type_keys_after(vni: &mut Vni, before: &str, keys: &str) -> String {
+        vni.set_buffer(before);
+        let mut document = before.to_string();
+        for ch in keys.chars() {
+            let key = if ch == BACKSPACE {
+                document.pop();
+                PhysicKey::new(keycodes::KEY_BACKSPACE, None)
+            } else {
+                document.push(ch);
+                PhysicKey::from_typed_char(ch).unwrap()
+            };
+            let actions = vni.handle_key(key);
+            document = apply_actions(&document, &actions);
+        }
+        document
+    }
+
+    fn observed(keys: &str) -> Vec<Transformation> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut vni = Vni::new();
+        let recorder = Arc::clone(&seen);
+        vni.set_observer(Box::new(move |transformation| {
+            recorder.lock().unwrap().push(*transformation)
+        }));
+        type_keys(&mut vni, keys);
+        let transformations = seen.lock().unwrap().clone();
+        transformations
+    }
+
+    #[test]
+    fn observer_sees_each_transformation() {
+        let change = |index, old, new, trigger| Transformation { index, old, new, trigger };
+        assert_eq!(observed("a1"), [change(0, 'a', 'á', '1')]);
+        assert_eq!(observed("a16"), [change(0, 'a', 'á', '1'), change(0, 'á', 'ấ', '6')]);
+        assert_eq!(observed("dd9"), [change(0, 'd', 'đ', '9')]);
+        assert_eq!(observed("ba"), []);
+    }
+
+    fn assert_composes(vni: &mut Vni, cases: &[(&str, &str)]) {
+        for &(keys, expected) in cases {
+            assert_eq!(type_keys(vni, keys), expected, "typing {}", keys);
+        }
+    }
+
+    #[test]
+    fn circumflex_skips_the_glide_before_e() {
+        assert_composes(&mut Vni::new(), &[
+            ("que6", "quê"),
+            ("hue65", "huệ"),
+            ("Que61", "Quế"),
+            ("khoe2", "khoè"),
+            ("khoeo2", "khoèo"),
+        ]);
+    }
+
+    #[test]
+    fn soft_commit_edits_the_last_syllable() {
+        let mut vni = Vni::builder().soft_commit_window(3).build();
+        assert_composes(&mut vni, &[
+            ("hoa 1", "hoá "),
+            ("tieng 61", "tiếng "),
+            ("hoa  2", "hoà  "),
+            ("hoa x1", "hoa x1"),
+        ]);
+    }
+
+    #[test]
+    fn soft_commit_window_runs_out() {
+        let mut vni = Vni::builder().soft_commit_window(3).build();
+        assert_composes(&mut vni, &[("hoa   1", "hoá   "), ("hoa    1", "hoa    1")]);
+        assert_composes(&mut Vni::new(), &[("hoa 1", "hoa 1")]);
+    }
+
+    #[test]
+    fn backspace_resumes_the_soft_committed_syllable() {
+        let mut vni = Vni::builder().soft_commit_window(3).build();
+        assert_composes(&mut vni, &[("hoa ⌫1", "hoá"), ("hoa  ⌫⌫1", "hoá")]);
+    }
+
+    #[test]
+    fn y_takes_the_circumflex_a_but_not_the_breve() {
+        assert_composes(&mut Vni::new(), &[
+            ("may", "may"),
+            ("may6", "mây"),
+            ("bay6", "bây"),
+            ("ay8", "ay8"),
+            ("may8", "may8"),
+            ("man8", "măn"),
+        ]);
+    }
+
+    #[test]
+    fn triggers_can_be_remapped() {
+        let mut vni = Vni::builder().trigger_acute('s').trigger_circumflex('q').build();
+        assert_composes(&mut vni, &[("as", "á"), ("eqs", "ế"), ("a1", "a1"), ("e6", "e6")]);
+    }
+
+    #[test]
+    fn smart_only_composes_vietnamese_syllables() {
+        let mut vni = Vni::builder().smart(true).build();
+        assert_composes(&mut vni, &[
+            ("file1", "file1"),
+            ("jazz1", "jazz1"),
+            ("http2", "http2"),
+            ("tieng61", "tiếng"),
+            ("d9", "đ"),
+        ]);
+        assert_composes(&mut Vni::new(), &[("file1", "fíle")]);
+    }
+
+    #[test]
+    fn tone_style_moves_the_tone_of_open_oa_oe_uy() {
+        let open = [("hoa2", "hoà", "hòa"), ("khoe3", "khoẻ", "khỏe"), ("thuy1", "thuý", "thúy")];
+        let closed = [("hoan1", "hoán"), ("khoen3", "khoẻn"), ("quy1", "quý")];
+        let mut modern = Vni::builder().tone_style(ToneStyle::Modern).build();
+        let mut classic = Vni::builder().tone_style(ToneStyle::Classic).build();
+        for &(keys, modern_text, classic_text) in open.iter() {
+            assert_composes(&mut modern, &[(keys, modern_text)]);
+            assert_composes(&mut classic, &[(keys, classic_text)]);
+        }
+        assert_composes(&mut modern, &closed);
+        assert_composes(&mut classic, &closed);
+    }
+
+    #[test]
+    fn builder_defaults_to_the_new_config() {
+        let built = Vni::builder().build();
+        assert_eq!(format!("{:?}", built.config()), format!("{:?}", Vni::new().config()));
+        let vni = Vni::builder().smart(true).tone_style(ToneStyle::Classic).build();
+        assert!(vni.config().smart);
+        assert_eq!(vni.config().tone_style, ToneStyle::Classic);
+        assert_eq!(vni.config().trigger_acute, '1');
+    }
+
+    #[test]
+    fn tone_goes_on_the_middle_of_oai_and_oay() {
+        let cases = [("khoai1", "khoái"), ("ngoai2", "ngoài"), ("hoai3", "hoải"), ("ngoay1", "ngoáy")];
+        assert_composes(&mut Vni::new(), &cases);
+        assert_composes(&mut Vni::builder().tone_style(ToneStyle::Classic).build(), &cases);
+    }
+
+    #[test]
+    fn backspace_right_after_a_tone_votes_for_the_other_style() {
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "hoa1⌫");
+        assert_eq!(vni.suggested_style(), ToneStyle::Classic);
+    }
+
+    #[test]
+    fn committing_an_open_syllable_votes_for_its_style() {
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "hoa1⌫a1 hoa1 ");
+        assert_eq!(vni.suggested_style(), ToneStyle::Modern);
+        let mut vni = Vni::builder().tone_style(ToneStyle::Classic).build();
+        type_keys(&mut vni, "hoa1 khoe3 ");
+        assert_eq!(vni.suggested_style(), ToneStyle::Classic);
+    }
+
+    #[test]
+    fn tied_votes_keep_the_configured_style() {
+        let vni = Vni::builder().tone_style(ToneStyle::Classic).build();
+        assert_eq!(vni.suggested_style(), ToneStyle::Classic);
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "hoa1⌫a1 ");
+        assert_eq!(vni.suggested_style(), ToneStyle::Modern);
+    }
+
+    #[test]
+    fn tone_placement() {
+        // keys, modern, classic
+        let cases = [
+            ("ua1", "úa", "úa"),
+            ("mua2", "mùa", "mùa"),
+            ("gia3", "giả", "giả"),
+            ("quy1", "quý", "quý"),
+            ("hoa2", "hoà", "hòa"),
+            ("khoe3", "khoẻ", "khỏe"),
+            ("thuy1", "thuý", "thúy"),
+            ("toan16", "toấn", "toấn"),
+            ("khoai1", "khoái", "khoái"),
+            ("khuay61", "khuấy", "khuấy"),
+            ("tieu63", "tiểu", "tiểu"),
+            ("tuoi72", "tười", "tười"),
+            ("nghieng64", "nghiễng", "nghiễng"),
+            ("nghienh1", "nghiénh", "nghiénh"),
+            ("nguyen61", "nguyến", "nguyến"),
+            ("chuong65", "chuộng", "chuộng"),
+            ("thuong72", "thường", "thường"),
+            ("huong71", "hướng", "hướng"),
+            ("giuong72", "giường", "giường"),
+        ];
+        let mut modern = Vni::builder().tone_style(ToneStyle::Modern).build();
+        let mut classic = Vni::builder().tone_style(ToneStyle::Classic).build();
+        for &(keys, modern_text, classic_text) in cases.iter() {
+            assert_composes(&mut modern, &[(keys, modern_text)]);
+            assert_composes(&mut classic, &[(keys, classic_text)]);
+        }
+    }
+
+    #[test]
+    fn tone_goes_on_a_vowel_given_with_its_diacritic() {
+        let mut vni = Vni::new();
+        assert_eq!(type_keys_after(&mut vni, "â", "1"), "ấ");
+        assert_eq!(type_keys_after(&mut vni, "ư", "5"), "ự");
+        assert_eq!(type_keys_after(&mut vni, "thơ", "2"), "thờ");
+        assert_eq!(type_keys_after(&mut vni, "tiê", "ng1"), "tiếng");
+    }
+
+    #[test]
+    fn repeating_the_tone_takes_it_off() {
+        assert_composes(&mut Vni::new(), &[
+            ("a11", "a"),
+            ("a111", "á"),
+            ("a161", "â"),
+            ("a611", "â"),
+            ("u755", "ư"),
+            ("a12", "à"),
+        ]);
+        let mut vni = Vni::builder().repeat_removes_tone(false).build();
+        assert_composes(&mut vni, &[("a11", "á"), ("a161", "ấ")]);
+    }
+
+    #[test]
+    fn shifted_digits_are_symbols_not_triggers() {
+        assert_composes(&mut Vni::new(), &[("a^", "a^"), ("a!", "a!"), ("a&", "a&"), ("A6", "Â")]);
+        // caps lock leaves the digit row alone
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "a");
+        let six = PhysicKey::from_char('6', Some(KeyCap::CapsLock)).unwrap();
+        assert_eq!(apply_actions("a6", &vni.handle_key(six)), "â");
+    }
+
+    #[test]
+    fn backspace_drops_a_composed_char_without_actions() {
+        let backspace = || PhysicKey::new(keycodes::KEY_BACKSPACE, None);
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "a1");
+        assert_eq!(vni.handle_key(backspace()), []);
+        assert_eq!(vni.preview(), (String::new(), 0));
+        type_keys(&mut vni, "uong72");
+        assert_eq!(vni.handle_key(backspace()), []);
+        assert_eq!(vni.preview(), ("ườn".to_string(), 3));
+        assert_composes(&mut vni, &[("uong72⌫⌫1", "ướ"), ("a1⌫b", "b")]);
+    }
+
+    #[test]
+    fn removing_the_tone_keeps_i_and_y_as_typed() {
+        let cases = [("my40", "my"), ("thy10", "thy"), ("ti10", "ti"), ("tieng610", "tiêng")];
+        assert_composes(&mut Vni::new(), &cases);
+        // respelling ie runs after the trigger and never undoes itself
+        let mut vni = Vni::builder().initial_ie_as_ye(true).build();
+        assert_composes(&mut vni, &cases);
+        assert_composes(&mut vni, &[("ien61", "yến"), ("ien610", "yên")]);
+    }
+
+    #[test]
+    fn breve_only_goes_on_a() {
+        assert_composes(&mut Vni::new(), &[
+            ("an81", "ắn"),
+            ("ang82", "ằng"),
+            ("a18", "ắ"),
+            ("a81", "ắ"),
+            ("a82", "ằ"),
+            ("a83", "ẳ"),
+            ("a84", "ẵ"),
+            ("a85", "ặ"),
+            ("A83", "Ẳ"),
+            ("e8", "e8"),
+            ("i8", "i8"),
+            ("u78", "ư8"),
+            ("thuong78", "thương8"),
+        ]);
+    }
+
+    #[test]
+    fn emoji_and_cjk_end_the_syllable() {
+        let mut vni = Vni::new();
+        assert_eq!(apply_actions("", &vni.handle_text("a😀1")), "a😀1");
+        assert_eq!(apply_actions("", &vni.handle_text("中1")), "中1");
+        assert_eq!(apply_actions("", &vni.handle_text("中a1")), "中á");
+        assert_eq!(type_keys_after(&mut vni, "a😀", "1"), "a😀1");
+        assert_eq!(type_keys_after(&mut vni, "中", "1"), "中1");
+        assert_eq!(vni.preview(), ("1".to_string(), 1));
+        assert!(!util::is_vietnamese_char('😀'));
+        assert!(!util::is_vietnamese_char('中'));
+        assert!(util::is_vietnamese_char('ế'));
+    }
+
+    #[test]
+    fn lone_gi_takes_the_tone_on_its_i() {
+        assert_composes(&mut Vni::new(), &[
+            ("gi2", "gì"),
+            ("gi1", "gí"),
+            ("Gi2", "Gì"),
+            ("gin2", "gìn"),
+            ("gia2", "già"),
+            ("giu3", "giủ"),
+            ("gieng61", "giếng"),
+        ]);
+    }
+
+    #[test]
+    fn context_before_the_caret_keeps_composing() {
+        let mut vni = Vni::new();
+        vni.set_context("tiế", "");
+        let actions = vni.handle_key(PhysicKey::from_typed_char('2').unwrap());
+        assert_eq!(apply_actions("tiế2", &actions), "tiề");
+        assert_eq!(vni.preview(), ("tiề".to_string(), 3));
+    }
+
+    #[test]
+    fn capital_vowel_starting_the_word_keeps_its_case() {
+        assert_composes(&mut Vni::new(), &[
+            ("Ong6", "Ông"),
+            ("Em", "Em"),
+            ("Anh1", "Ánh"),
+            ("ANH1", "ÁNH"),
+            ("An81", "Ắn"),
+            ("Oi3", "Ỏi"),
+            ("Uong72", "Ường"),
+        ]);
+    }
+
+    #[test]
+    fn crossed_d_is_never_the_tone_vowel() {
+        assert_eq!(util::remove_accents('đ'), 'đ');
+        assert_eq!(util::clean_char('đ'), 'd');
+        assert_composes(&mut Vni::new(), &[
+            ("d91", "đ1"),
+            ("d9a1", "đá"),
+            ("da91", "đá"),
+            ("D9a2", "Đà"),
+        ]);
+    }
+
+    #[test]
+    fn triggers_after_backspacing_to_empty_stay_literal() {
+        let mut vni = Vni::new();
+        assert_eq!(type_keys(&mut vni, "a1⌫"), "");
+        assert_eq!(vni.preview(), (String::new(), 0));
+        for trigger in "1234567890".chars() {
+            let keys = format!("a1⌫{}", trigger);
+            assert_eq!(type_keys(&mut vni, &keys), trigger.to_string());
+        }
+    }
+
+    #[test]
+    fn tone_goes_on_the_a_of_uay() {
+        let cases = [("khuay6", "khuây"), ("khuay61", "khuấy"), ("khuay16", "khuấy"), ("khuay1", "khuáy")];
+        assert_composes(&mut Vni::new(), &cases);
+        assert_composes(&mut Vni::builder().tone_style(ToneStyle::Classic).build(), &cases);
+    }
+
+    // Type `keys` like `type_keys` but the way a host intercepting keys
+    // does, with `handle_key_response`
+    fn intercept_keys(vni: &mut Vni, keys: &str) -> String {
+        vni.set_buffer("");
+        let mut document = String::new();
+        for ch in keys.chars() {
+            let response = vni.handle_key_response(PhysicKey::from_typed_char(ch).unwrap());
+            if !response.swallow_original {
+                document.push(ch);
+            }
+            document = apply_actions(&document, &response.actions);
+        }
+        document
+    }
+
+    #[test]
+    fn key_response_swallows_triggers_that_compose() {
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "a");
+        let response = vni.handle_key_response(PhysicKey::from_typed_char('1').unwrap());
+        assert!(response.swallow_original);
+        assert_eq!(response.actions, [Action::Backspace(1), Action::Insert('á')]);
+        let response = vni.handle_key_response(PhysicKey::from_typed_char('n').unwrap());
+        assert!(!response.swallow_original);
+        assert_eq!(response.actions, []);
+    }
+
+    #[test]
+    fn key_response_lets_other_keys_through() {
+        let mut vni = Vni::new();
+        for key in [
+            PhysicKey::from_typed_char('b').unwrap(),
+            PhysicKey::from_typed_char('1').unwrap(),
+            PhysicKey::from_typed_char(' ').unwrap(),
+            PhysicKey::new(KEY_F1, None),
+        ].iter() {
+            let response = vni.handle_key_response(key.clone());
+            assert!(!response.swallow_original);
+            assert_eq!(response.actions, []);
+        }
+    }
+
+    #[test]
+    fn key_response_types_what_handle_key_does() {
+        let keys = ["tieng61", "a1a2", "hoa2 ", "1a", "Trung-Quoc65", "uong72"];
+        let configs = [
+            VniConfig::default(),
+            Vni::builder().output_mode(OutputMode::ReplaceWord).build().config().clone(),
+            Vni::builder().tone_style(ToneStyle::Classic).build().config().clone(),
+        ];
+        for config in configs.iter() {
+            let mut vni = Vni::with_config(config.clone());
+            for &keys in keys.iter() {
+                let typed = type_keys(&mut vni, keys);
+                assert_eq!(intercept_keys(&mut vni, keys), typed, "typing {}", keys);
+            }
+        }
+    }
+
+    #[test]
+    fn key_response_swallows_every_letter_when_replacing_words() {
+        let mut vni = Vni::builder().output_mode(OutputMode::ReplaceWord).build();
+        let response = vni.handle_key_response(PhysicKey::from_typed_char('t').unwrap());
+        assert!(response.swallow_original);
+        assert_eq!(response.actions, [Action::ReplaceWord(0, "t".to_string())]);
+        let response = vni.handle_key_response(PhysicKey::from_typed_char('a').unwrap());
+        assert_eq!(response.actions, [Action::ReplaceWord(1, "ta".to_string())]);
+        assert_eq!(intercept_keys(&mut vni, "tieng61"), "tiếng");
+    }
+
+    #[test]
+    fn hook_and_tilde_go_on_every_diacritic_vowel() {
+        assert_composes(&mut Vni::new(), &[
+            ("a63", "ẩ"), ("a64", "ẫ"),
+            ("a83", "ẳ"), ("a84", "ẵ"),
+            ("e63", "ể"), ("e64", "ễ"),
+            ("o63", "ổ"), ("o64", "ỗ"),
+            ("o73", "ở"), ("o74", "ỡ"),
+            ("u73", "ử"), ("u74", "ữ"),
+            ("E63", "Ể"), ("O74", "Ỡ"),
+        ]);
+        for &tone in [Tone::HookAbove, Tone::Tilde].iter() {
+            for ch in "âăêôơưÂĂÊÔƠƯ".chars() {
+                let toned = character_map::lookup(&util::tone_map(tone), ch);
+                assert!(toned.is_some(), "{:?} on {}", tone, ch);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_typing_nothing_leave_the_syllable_alone() {
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "tie6");
+        let before = vni.preview();
+        assert_eq!(vni.handle_key(PhysicKey::new(KEY_F1, None)), []);
+        assert_eq!(vni.preview(), before);
+        let actions = vni.handle_key(PhysicKey::from_typed_char('1').unwrap());
+        assert_eq!(apply_actions("tiê1", &actions), "tiế");
+    }
+
+    #[test]
+    fn tone_goes_on_the_o_of_uo() {
+        let cases = [
+            ("buon62", "buồn"),
+            ("luong62", "luồng"),
+            ("suong65", "suộng"),
+            ("cuoc65", "cuộc"),
+            ("uon61", "uốn"),
+        ];
+        assert_composes(&mut Vni::new(), &cases);
+        assert_composes(&mut Vni::builder().tone_style(ToneStyle::Classic).build(), &cases);
+    }
+
+    #[test]
+    fn replace_word_mode_gives_the_whole_syllable_each_key() {
+        let mut vni = Vni::builder().output_mode(OutputMode::ReplaceWord).build();
+        let replaced = "tieng61"
+            .chars()
+            .map(|ch| vni.handle_key(PhysicKey::from_typed_char(ch).unwrap()))
+            .collect::<Vec<Vec<Action>>>();
+        let replace = |amount, word: &str| vec![Action::ReplaceWord(amount, word.to_string())];
+        assert_eq!(replaced, [
+            replace(1, "t"),
+            replace(2, "ti"),
+            replace(3, "tie"),
+            replace(4, "tien"),
+            replace(5, "tieng"),
+            replace(6, "tiêng"),
+            replace(6, "tiếng"),
+        ]);
+        assert_composes(&mut vni, &[("tieng61", "tiếng"), ("Trung-Quoc65", "Trung-Quộc")]);
+    }
+
+    #[test]
+    fn marks_keep_the_case_of_each_letter() {
+        assert_composes(&mut Vni::new(), &[
+            ("tIeng61", "tIếng"),
+            ("TiEng61", "TiẾng"),
+            ("nGuOi72", "nGưỜi"),
+            ("hOa2", "hOà"),
+            ("DuOng79", "ĐưƠng"),
+        ]);
+    }
+
+    #[test]
+    fn vowel_typed_again_after_a_tone_is_a_second_vowel() {
+        let mut vni = Vni::new();
+        assert_eq!(type_keys(&mut vni, "a1a"), "áa");
+        assert_eq!(vni.preview(), ("áa".to_string(), 2));
+        assert_composes(&mut vni, &[("a1a2", "àa"), ("a1a0", "aa"), ("hoa1a2", "hoàa")]);
+    }
+
+    #[test]
+    fn w_puts_the_horn_only_when_enabled() {
+        let mut vni = Vni::builder().w_as_horn(true).build();
+        assert_composes(&mut vni, &[("uw", "ư"), ("Uw", "Ư"), ("tuow", "tươ"), ("bw", "bw")]);
+        assert_composes(&mut Vni::new(), &[("uw", "uw"), ("bw", "bw"), ("u7", "ư")]);
+    }
+
+    #[test]
+    fn key_response_leaves_the_actions_of_the_commit_key_alone() {
+        let dictionary = ["to"].iter().map(|word| word.to_string()).collect();
+        let mut vni = Vni::builder()
+            .commit_key(keycodes::KEY_ENTER)
+            .dictionary(dictionary)
+            .revert_if_not_in_dict(true)
+            .build();
+        let enter = PhysicKey::new(keycodes::KEY_ENTER, None);
+        intercept_keys(&mut vni, "to1");
+        let response = vni.handle_key_response(enter.clone());
+        assert!(response.swallow_original);
+        assert_eq!(response.actions, [Action::Backspace(1), Action::Insert('o')]);
+        assert_eq!(intercept_keys(&mut vni, "to1\n"), "to");
+        assert_eq!(intercept_keys(&mut vni, "to\n"), "to");
+        assert_eq!(vni.handle_key_response(enter), KeyResponse {
+            actions: Vec::new(),
+            swallow_original: true,
+        });
+    }
+
+    #[test]
+    fn words_missing_from_the_dictionary_lose_their_marks() {
+        let dictionary = ["tiếng", "việt"].iter().map(|word| word.to_string()).collect();
+        let mut vni = Vni::builder().dictionary(dictionary).revert_if_not_in_dict(true).build();
+        assert_composes(&mut vni, &[
+            ("tieng61 ", "tiếng "),
+            ("Vie65t ", "Việt "),
+            ("to1 ", "to "),
+            ("top1-", "top-"),
+            ("to1", "tó"),
+        ]);
+        assert_composes(&mut Vni::new(), &[("to1 ", "tó ")]);
+    }
+
+    #[test]
+    fn a_vowel_after_a_coda_starts_the_next_syllable() {
+        assert_composes(&mut Vni::new(), &[
+            ("anhem1", "anhém"),
+            ("emoi1", "emói"),
+            ("hoan1", "hoán"),
+            ("nghieng61", "nghiếng"),
+        ]);
+    }
+
+    #[test]
+    fn telex_tone_letters_typed_twice_stay_typed() {
+        let mut vni = Vni::builder().telex_tones().build();
+        assert_composes(&mut vni, &[
+            ("tieng6s", "tiếng"),
+            ("hoaf", "hoà"),
+            ("sao", "sao"),
+            ("as", "á"),
+            ("ass", "as"),
+            ("hoaff", "hoaf"),
+            ("a1", "a1"),
+        ]);
+        let mut vni = Vni::builder().telex_tones().smart(true).build();
+        assert_composes(&mut vni, &[("bass", "bas"), ("tieng6ss", "tiêngs"), ("jazz", "jazz")]);
+        assert_composes(&mut Vni::new(), &[("a11", "a")]);
+    }
+
+    #[test]
+    fn zero_gives_back_the_syllable_before_its_tone() {
+        let onsets = [
+            "", "b", "c", "ch", "d", "đ", "g", "gh", "gi", "k", "kh", "m", "ng", "ngh",
+            "nh", "ph", "qu", "th", "tr", "x",
+        ];
+        let nuclei = [
+            "a", "ă", "â", "e", "ê", "i", "o", "ô", "ơ", "u", "ư", "y", "ai", "ao", "au",
+            "ay", "âu", "ây", "eo", "êu", "ia", "iê", "iu", "oa", "oă", "oe", "oi", "ôi",
+            "ơi", "ua", "uâ", "uê", "ui", "uô", "uy", "ưa", "ưi", "ươ", "ưu", "oai", "oay",
+            "uôi", "ươi", "ươu", "uyê", "iêu", "yê", "yêu",
+        ];
+        let codas = ["", "c", "ch", "m", "n", "ng", "nh", "p", "t"];
+        let mut vni = Vni::new();
+        for onset in onsets.iter() {
+            for nucleus in nuclei.iter() {
+                for coda in codas.iter() {
+                    let base = format!("{}{}{}", onset, nucleus, coda);
+                    // combinations spelled wrong (gha, ce, ka) are left out,
+                    // the tone would go on something that isn't a syllable
+                    let chars = base.chars().collect::<Vec<_>>();
+                    if !util::is_valid_syllable(&chars) {
+                        continue;
+                    }
+                    for trigger in "12345".chars() {
+                        let keys = format!("{}0", trigger);
+                        assert_eq!(type_keys_after(&mut vni, &base, &keys), base, "{}{}", base, keys);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smart_leaves_the_english_words_of_a_mixed_sentence_alone() {
+        let mut vni = Vni::builder().smart(true).build();
+        assert_composes(&mut vni, &[
+            ("tieng61 Viet65 la2 mot65 language", "tiếng Việt là một language"),
+            ("toi6 dung2 file1 config", "tôi dùng file1 config"),
+            ("check email truoc71 khi deploy", "check email trước khi deploy"),
+            ("the3 Windows 10 va2 Linux", "thẻ Windows 10 và Linux"),
+        ]);
+    }
+
+    #[test]
+    fn repeating_the_horn_takes_it_off() {
+        assert_composes(&mut Vni::new(), &[
+            ("u77", "u"),
+            ("uo77", "uo"),
+            ("duong77", "duong"),
+            ("o77", "o"),
+            ("duong727", "duòng"),
+        ]);
+    }
+
+    #[test]
+    fn tone_goes_on_the_horned_vowel_of_uou_and_uu() {
+        assert_composes(&mut Vni::new(), &[
+            ("huou7", "hươu"),
+            ("huou72", "hườu"),
+            ("ruou725", "rượu"),
+            ("cuu72", "cừu"),
+            ("chiu1", "chíu"),
+        ]);
+    }
+
+    #[test]
+    fn punctuation_tones_only_go_after_a_vowel() {
+        let mut vni = Vni::builder()
+            .trigger_acute('\'')
+            .trigger_grave('`')
+            .punctuation_tone_after_vowel(true)
+            .build();
+        assert_composes(&mut vni, &[
+            ("don't", "don't"),
+            ("it's", "it's"),
+            ("can't stop", "can't stop"),
+            ("a'", "á"),
+            ("ba`", "bà"),
+        ]);
+    }
+
+    #[test]
+    fn pending_tone_waits_for_the_next_vowel() {
+        let mut vni = Vni::builder().pending_tone(true).build();
+        assert_composes(&mut vni, &[("1a", "á"), ("1b", "1b"), ("1ba", "1ba"), ("2o", "ò")]);
+        assert_composes(&mut Vni::new(), &[("1a", "1a")]);
+    }
+
+    #[test]
+    fn foreign_letters_keep_the_word_as_typed() {
+        let mut vni = Vni::builder().foreign_letters(vec!['f', 'j', 'w', 'z']).build();
+        assert_composes(&mut vni, &[
+            ("jazz", "jazz"),
+            ("jazz1", "jazz1"),
+            ("file", "file"),
+            ("file1", "file1"),
+            ("tieng61", "tiếng"),
+        ]);
+    }
+
+    #[test]
+    fn collapse_double_o_decides_what_oo_takes() {
+        let cases = [("oo6", "ô"), ("boong6", "bông"), ("xoong6", "xông")];
+        assert_composes(&mut Vni::builder().collapse_double_o(true).build(), &cases);
+        let mut vni = Vni::builder().collapse_double_o(false).build();
+        assert_composes(&mut vni, &[("oo6", "oo6"), ("boong6", "boong6"), ("xoong6", "xoong6")]);
+    }
+
+    #[test]
+    fn disabled_marks_type_their_trigger() {
+        let mut vni = Vni::builder().disable_mark(Mark::Breve).build();
+        assert_composes(&mut vni, &[("a8", "a8"), ("an8", "an8"), ("a1", "á"), ("a6", "â")]);
+    }
+
+    #[test]
+    fn diacritics_on_the_same_vowel_replace_each_other() {
+        assert_composes(&mut Vni::new(), &[
+            ("o67", "ơ"),
+            ("o76", "ô"),
+            ("uo76", "uô"),
+            ("duong761", "duống"),
+            ("tho67", "thơ"),
+        ]);
+    }
+
+    #[test]
+    fn commit_key_ends_the_syllable() {
+        // type `keys` after F1 without resetting the engine
+        let after_f1 = |vni: &mut Vni, before: &str, keys: &str| {
+            let mut document = type_keys(vni, before);
+            assert_eq!(vni.handle_key(PhysicKey::new(KEY_F1, None)), []);
+            for ch in keys.chars() {
+                document.push(ch);
+                let actions = vni.handle_key(PhysicKey::from_typed_char(ch).unwrap());
+                document = apply_actions(&document, &actions);
+            }
+            document
+        };
+        let mut vni = Vni::builder().commit_key(KEY_F1).build();
+        assert_eq!(after_f1(&mut vni, "hoa1", "1an1"), "hoá1án");
+        assert_eq!(after_f1(&mut vni, "hoa", "1"), "hoa1");
+        // without it F1 is any key typing nothing, the syllable goes on
+        assert_eq!(after_f1(&mut Vni::new(), "hoa", "1"), "hoá");
+    }
+
+    #[test]
+    fn tone_goes_on_the_second_o_of_loanwords() {
+        assert_composes(&mut Vni::new(), &[
+            ("xoong2", "xoòng"),
+            ("Xoong3", "Xoỏng"),
+            ("coong", "coong"),
+            ("coong2", "coòng"),
+            ("boong5", "boọng"),
+        ]);
+    }
+
+    #[test]
+    fn undo_all_leaves_the_letters_as_typed() {
+        let mut vni = Vni::new();
+        let document = type_keys(&mut vni, "tieng61");
+        assert_eq!(document, "tiếng");
+        let document = apply_actions(&document, &vni.undo_all());
+        assert_eq!(document, "tieng");
+        assert_eq!(vni.preview(), ("tieng".to_string(), 5));
+        let actions = vni.handle_key(PhysicKey::from_typed_char('2').unwrap());
+        assert_eq!(apply_actions("tieng2", &actions), "tièng");
+    }
+
+    #[test]
+    fn a_diacritic_goes_on_a_single_o() {
+        assert_composes(&mut Vni::new(), &[
+            ("oao6", "oaô"),
+            ("oao7", "oaơ"),
+            ("oco6", "ocô"),
+            ("oi6", "ôi"),
+            ("o o6", "o ô"),
+            ("uo7", "ươ"),
+        ]);
+    }
+
+    #[test]
+    fn restoring_a_snapshot_brings_the_syllable_back() {
+        let mut vni = Vni::builder().soft_commit_window(3).build();
+        type_keys(&mut vni, "tieng6");
+        let snapshot = vni.snapshot();
+        type_keys(&mut vni, "hoa1 ");
+        vni.restore(snapshot.clone());
+        assert_eq!(vni.snapshot(), snapshot);
+        let actions = vni.handle_key(PhysicKey::from_typed_char('2').unwrap());
+        assert_eq!(apply_actions("tiêng2", &actions), "tiềng");
+        type_keys(&mut vni, "hoa ");
+        let snapshot = vni.snapshot();
+        type_keys(&mut vni, "ba");
+        vni.restore(snapshot);
+        let actions = vni.handle_key(PhysicKey::from_typed_char('1').unwrap());
+        assert_eq!(apply_actions("hoa 1", &actions), "hoá ");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshots_round_trip_through_serde() {
+        let mut vni = Vni::builder().soft_commit_window(3).build();
+        type_keys(&mut vni, "hoa1 ");
+        let snapshot = vni.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<VniSnapshot>(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn a_long_run_of_letters_keeps_the_buffer_bounded() {
+        for &policy in [OverflowPolicy::DropOldest, OverflowPolicy::CommitAndReset].iter() {
+            let mut vni = Vni::builder().max_buffer_len(8).overflow_policy(policy).build();
+            let mut document = String::new();
+            for ch in "x".repeat(2_000).chars().chain("hoa1".chars()) {
+                document.push(ch);
+                let actions = vni.handle_key(PhysicKey::from_typed_char(ch).unwrap());
+                document = apply_actions(&document, &actions);
+                assert!(vni.preview().0.chars().count() <= 8, "{:?}", policy);
+            }
+            assert_eq!(document.chars().count(), 2_003, "{:?}", policy);
+            assert!(document.ends_with("xhoá"), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn last_vowel_puts_the_tone_on_the_last_vowel() {
+        let mut vni = Vni::builder().tone_style(ToneStyle::LastVowel).build();
+        assert_composes(&mut vni, &[
+            ("hoa1", "hoá"),
+            ("mua2", "muà"),
+            ("qua1", "quá"),
+            ("qu1", "qu1"),
+            ("Qu1", "Qu1"),
+            ("gi2", "gì"),
+        ]);
+    }
+
+    #[test]
+    fn tone_maps_missing_a_vowel_are_rejected() {
+        let mut map = util::tone_map(Tone::Acute);
+        let missing = map.iter().position(|&(vowel, _)| vowel == 'ê').unwrap();
+        map[missing] = ('x', 'x');
+        let built = Vni::builder().tone_map(Tone::Acute, map).try_build();
+        assert_eq!(built.err(), Some(ConfigError::MissingVowel { tone: Tone::Acute, vowel: 'ê' }));
+        let mut map = util::tone_map(Tone::Acute);
+        let duplicate = map.iter().position(|&(vowel, _)| vowel == 'Ê').unwrap();
+        map[duplicate] = ('E', 'É');
+        let built = Vni::builder().tone_map(Tone::Acute, map).try_build();
+        assert_eq!(built.err(), Some(ConfigError::DuplicateVowel { tone: Tone::Acute, vowel: 'E' }));
+        let built = Vni::builder().tone_map(Tone::Acute, util::tone_map(Tone::Acute)).try_build();
+        assert!(built.is_ok());
+    }
+
+    #[test]
+    fn enter_commits_a_composed_word_without_a_newline() {
+        let mut vni = Vni::builder().enter(EnterBehavior::Commit).build();
+        assert_composes(&mut vni, &[("hoa1\n", "hoá"), ("hoa1\n\n", "hoá\n"), ("hoa\n", "hoa\n")]);
+        assert!(!vni.is_composing());
+        assert_composes(&mut Vni::new(), &[("hoa1\n", "hoá\n"), ("hoa1\n1", "hoá\n1")]);
+    }
+
+    #[test]
+    fn compose_from_triggers_covers_every_vowel() {
+        // toneless then the tones of 1 to 5, a diacritic the letter can't
+        // take is skipped
+        let rows = [
+            ('a', "", "aáàảãạ"),
+            ('a', "6", "âấầẩẫậ"),
+            ('a', "7", "aáàảãạ"),
+            ('a', "8", "ăắằẳẵặ"),
+            ('e', "", "eéèẻẽẹ"),
+            ('e', "6", "êếềểễệ"),
+            ('e', "8", "eéèẻẽẹ"),
+            ('i', "", "iíìỉĩị"),
+            ('i', "6", "iíìỉĩị"),
+            ('o', "", "oóòỏõọ"),
+            ('o', "6", "ôốồổỗộ"),
+            ('o', "7", "ơớờởỡợ"),
+            ('o', "8", "oóòỏõọ"),
+            ('u', "", "uúùủũụ"),
+            ('u', "7", "ưứừửữự"),
+            ('u', "6", "uúùủũụ"),
+            ('y', "", "yýỳỷỹỵ"),
+            ('y', "7", "yýỳỷỹỵ"),
+        ];
+        for &(base, diacritic, expected) in rows.iter() {
+            for (tone, expected) in ["", "1", "2", "3", "4", "5"].iter().zip(expected.chars()) {
+                let upper = expected.to_uppercase().next().unwrap();
+                // the tone and the diacritic can come in either order
+                for keys in [format!("{}{}", diacritic, tone), format!("{}{}", tone, diacritic)] {
+                    let triggers = keys.chars().collect::<Vec<_>>();
+                    assert_eq!(compose_from_triggers(base, &triggers), expected, "{}{}", base, keys);
+                    let upper_base = base.to_ascii_uppercase();
+                    assert_eq!(compose_from_triggers(upper_base, &triggers), upper, "{}", keys);
+                }
+            }
+        }
+        assert_eq!(compose_from_triggers('d', &['9']), 'đ');
+        assert_eq!(compose_from_triggers('D', &['9']), 'Đ');
+        assert_eq!(compose_from_triggers('b', &['1', '6']), 'b');
+    }
+
+    #[test]
+    fn hidden_separators_split_the_syllable_without_showing() {
+        let mut vni = Vni::builder().hidden_separators(vec!['|', '\u{200c}']).build();
+        assert_composes(&mut vni, &[("cu|a1", "cuá"), ("cua1", "cúa"), ("cu|a", "cua")]);
+        let actions = vni.handle_text("cu\u{200c}a1");
+        assert_eq!(apply_actions("", &actions), "cuá");
+    }
+
+    #[test]
+    fn applying_a_profile_mid_word_commits_or_keeps_the_syllable() {
+        let classic = Vni::builder().tone_style(ToneStyle::Classic).build().config().clone();
+        let telex = Vni::builder().telex_tones().build().config().clone();
+        // type `keys` after switching to `profile` with "hoa" composed
+        let after_switch = |profile: &VniConfig, commit: bool, keys: &str| {
+            let mut vni = Vni::new();
+            let mut document = type_keys(&mut vni, "hoa");
+            vni.apply_profile(profile, commit);
+            for ch in keys.chars() {
+                document.push(ch);
+                let actions = vni.handle_key(PhysicKey::from_typed_char(ch).unwrap());
+                document = apply_actions(&document, &actions);
+            }
+            assert_eq!(vni.config().tone_style, profile.tone_style);
+            document
+        };
+        assert_eq!(after_switch(&classic, false, "1"), "hóa");
+        assert_eq!(after_switch(&classic, true, "1"), "hoa1");
+        assert_eq!(after_switch(&classic, true, "1a1"), "hoa1á");
+        assert_eq!(after_switch(&telex, false, "s"), "hoá");
+        assert_eq!(after_switch(&telex, false, "1"), "hoa1");
+        assert_eq!(after_switch(&telex, true, "s"), "hoas");
+    }
+
+    #[test]
+    fn ia_never_takes_the_circumflex_but_gia_does() {
+        assert_composes(&mut Vni::new(), &[
+            ("bia6", "bia6"),
+            ("kia6", "kia6"),
+            ("bie6n", "biên"),
+            ("gia6y", "giây"),
+            ("gia6t", "giât"),
+        ]);
+    }
+
+    #[test]
+    fn keypad_digits_only_compose_with_keypad_triggers() {
+        // type "a" then 6 from `key`, give the document
+        let a_then = |vni: &mut Vni, key: u32| {
+            let document = type_keys(vni, "a");
+            apply_actions(&format!("{}6", document), &vni.handle_key(PhysicKey::new(key, None)))
+        };
+        let mut vni = Vni::builder().keypad_triggers(false).build();
+        assert_eq!(a_then(&mut vni, keycodes::KEY_KP_6), "a6");
+        assert_eq!(a_then(&mut vni, keycodes::KEY_6), "â");
+        let mut vni = Vni::builder().keypad_triggers(true).build();
+        assert_eq!(a_then(&mut vni, keycodes::KEY_KP_6), "â");
+    }
+
+    #[test]
+    fn apply_variant_replaces_the_picked_letter() {
+        let mut vni = Vni::new();
+        let document = type_keys(&mut vni, "ten");
+        let document = apply_actions(&document, &vni.apply_variant(1, 'ế'));
+        assert_eq!(document, "tến");
+        assert_eq!(vni.preview().0, "tến");
+        let document = apply_actions(&document, &vni.apply_variant(1, 'ệ'));
+        assert_eq!(document, "tện");
+        assert_eq!(vni.apply_variant(1, 'ố'), []);
+        assert_eq!(vni.apply_variant(3, 'ế'), []);
+        let document = type_keys(&mut vni, "ho1a");
+        assert_eq!(apply_actions(&document, &vni.apply_variant(2, 'à')), "hoà");
+    }
+
+    #[test]
+    fn resync_follows_a_document_edited_behind_the_engine() {
+        let mut vni = Vni::new();
+        type_keys(&mut vni, "tieng");
+        // the OS autocorrect turned it into tiêng
+        vni.resync("xin tiêng");
+        assert_eq!(vni.preview().0, "tiêng");
+        let actions = vni.handle_key(PhysicKey::from_typed_char('1').unwrap());
+        assert_eq!(apply_actions("xin tiêng1", &actions), "xin tiếng");
+        type_keys(&mut vni, "hoa");
+        vni.resync("hoan");
+        let actions = vni.handle_key(PhysicKey::from_typed_char('2').unwrap());
+        assert_eq!(apply_actions("hoan2", &actions), "hoàn");
+    }
+
+    #[test]
+    fn from_composed_text_picks_the_style_of_the_text() {
+        let continue_with = |text: &str, key: char| {
+            let mut vni = Vni::from_composed_text(text);
+            let actions = vni.handle_key(PhysicKey::from_typed_char(key).unwrap());
+            (vni.config().tone_style, apply_actions(&format!("{}{}", text, key), &actions))
+        };
+        assert_eq!(continue_with("hòa thuy", '1'), (ToneStyle::Classic, "hòa thúy".to_string()));
+        assert_eq!(continue_with("hoà thuy", '1'), (ToneStyle::Modern, "hoà thuý".to_string()));
+        assert_eq!(continue_with("xin chao", '2'), (ToneStyle::Modern, "xin chào".to_string()));
+        assert_eq!(continue_with("tiế", '2'), (ToneStyle::Modern, "tiề".to_string()));
+    }
+
+    #[test]
+    fn tones_after_an_onset_alone_stay_literal() {
+        assert_composes(&mut Vni::new(), &[
+            ("ng1", "ng1"),
+            ("tr2", "tr2"),
+            ("ngh3", "ngh3"),
+            ("qu1", "qu1"),
+            ("Qu5", "Qu5"),
+            ("nga1", "ngá"),
+            ("qua1", "quá"),
+        ]);
+    }
+
+    #[test]
+    fn reset_to_replaces_the_old_syllable_in_the_document() {
+        let mut vni = Vni::new();
+        let document = type_keys(&mut vni, "xin tieng61");
+        let document = apply_actions(&document, &vni.reset_to("tiền"));
+        assert_eq!(document, "xin tiền");
+        assert_eq!(vni.preview().0, "tiền");
+        let actions = vni.handle_key(PhysicKey::from_typed_char('1').unwrap());
+        assert_eq!(apply_actions(&format!("{}1", document), &actions), "xin tiến");
+        let document = type_keys(&mut vni, "hoa1");
+        assert_eq!(apply_actions(&document, &vni.reset_to("")), "");
+    }
+
+    #[test]
+    fn initial_ie_as_ye_spells_a_word_initial_ie_with_y() {
+        let mut vni = Vni::builder().initial_ie_as_ye(true).build();
+        assert_composes(&mut vni, &[
+            ("ieu6", "yêu"),
+            ("Ieu6", "Yêu"),
+            ("IEU6", "YÊU"),
+            ("ie6n1", "yến"),
+            ("tie6n", "tiên"),
+        ]);
+        let mut vni = Vni::builder().initial_ie_as_ye(false).build();
+        assert_composes(&mut vni, &[("ieu6", "iêu"), ("Ieu6", "Iêu"), ("ie6n1", "iến")]);
+    }
+
+    #[test]
+    fn handle_text_composes_each_word_on_its_own() {
+        let mut vni = Vni::new();
+        assert_eq!(apply_actions("", &vni.handle_text("xin chao2")), "xin chào");
+        let mut vni = Vni::new();
+        let text = apply_actions("", &vni.handle_text("hoa1. an2, 1"));
+        assert_eq!(text, "hoá. àn, 1");
+        let mut vni = Vni::new();
+        assert_eq!(apply_actions("", &vni.handle_text("a😀1")), "a😀1");
+    }
 }
@@ -0,0 +1,17 @@
+//! Escape composed text for transports and logs that only carry ASCII.
+//!
+//! ASCII is passed through as is, HTML special chars (`&`, `<`) included, so
+//! escape those first if the output ends up in a document.
+
+/// Replace every non ASCII char with its HTML hex entity (việt -> vi&#x1EC7;t)
+pub fn to_entities(text: &str) -> String {
+    text.chars()
+        .map(|ch| {
+            if ch.is_ascii() {
+                ch.to_string()
+            } else {
+                format!("&#x{:X};", ch as u32)
+            }
+        })
+        .collect()
+}
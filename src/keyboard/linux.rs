@@ -188,7 +188,7 @@ impl Keyboard for KeyboardHandler {
         let keysym = self.char_to_keysym(ch);
         unsafe {
             let mut keycode: i32 = XKeysymToKeycode(self.display, keysym.into()) as i32;
-            let need_remap = keycode > 255 || keycode < 8;
+            let need_remap = !(8..=255).contains(&keycode);
             if need_remap {
                 keycode = self.find_keycode_to_remap();
                 self.remap_scratch_keycode(keycode, keysym.into());
@@ -221,7 +221,8 @@ impl Keyboard for KeyboardHandler {
         let mask = KeyPressMask | KeyReleaseMask | FocusChangeMask;
         unsafe {
             let mut root = XDefaultRootWindow(self.display);
-            XGetInputFocus(self.display, &mut root, &mut xlib::RevertToParent);
+            let mut revert_to = xlib::RevertToParent;
+            XGetInputFocus(self.display, &mut root, &mut revert_to);
             XSelectInput(self.display, root, mask);
             loop {
                 XNextEvent(self.display, &mut ev);
@@ -308,7 +309,7 @@ impl Keyboard for KeyboardHandler {
                         XGetInputFocus(
                             self.display,
                             &mut root,
-                            &mut xlib::RevertToParent
+                            &mut revert_to
                         );
                         XSelectInput(self.display, root, mask);
                     },
@@ -0,0 +1,54 @@
+//! Send engine actions to the focused macOS application.
+//!
+//! Events are posted through Quartz, which only delivers them when the
+//! running binary (or the terminal hosting it) has been granted access in
+//! System Preferences > Security & Privacy > Privacy > Accessibility.
+//! Without that permission `CGEvent::post` silently drops every event.
+use crate::engine::Action;
+use core_graphics::event::{CGEvent, CGEventTapLocation, CGKeyCode};
+use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+
+// kVK_Delete, the backspace key on a mac keyboard
+const KEY_DELETE: CGKeyCode = 0x33;
+
+fn post_key(source: &CGEventSource, keycode: CGKeyCode, ch: Option<char>) {
+    for &key_down in &[true, false] {
+        let event = CGEvent::new_keyboard_event(source.clone(), keycode, key_down);
+        if let Ok(event) = event {
+            if let Some(ch) = ch {
+                // the keycode is ignored once a unicode string is attached
+                event.set_string(&ch.to_string());
+            }
+            event.post(CGEventTapLocation::HID);
+        }
+    }
+}
+
+/// Replay a list of actions returned by the engine as keyboard events.
+///
+/// Inserts are sent as unicode strings so composed vietnamese characters
+/// don't need to exist on the current keyboard layout, backspaces are sent
+/// as presses of the delete key.
+pub fn send_actions(actions: &[Action]) {
+    let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState);
+    if let Ok(source) = source {
+        for action in actions {
+            match *action {
+                Action::Insert(ch) => post_key(&source, 0, Some(ch)),
+                Action::Backspace(amount) => {
+                    for _ in 0..amount {
+                        post_key(&source, KEY_DELETE, None);
+                    }
+                }
+                Action::ReplaceWord(amount, ref word) => {
+                    for _ in 0..amount {
+                        post_key(&source, KEY_DELETE, None);
+                    }
+                    for ch in word.chars() {
+                        post_key(&source, 0, Some(ch));
+                    }
+                }
+            }
+        }
+    }
+}
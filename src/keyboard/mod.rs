@@ -6,6 +6,12 @@ mod linux;
 #[cfg(target_os = "linux")]
 use linux::KeyboardHandler;
 
+#[cfg(all(target_os = "macos", feature = "macos"))]
+pub mod macos;
+
+#[cfg(feature = "rdev")]
+pub mod rdev;
+
 pub trait Keyboard {
     fn backspace(&self, amount: usize);
     fn insert(&self, ch: char);
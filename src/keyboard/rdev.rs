@@ -0,0 +1,130 @@
+//! Turn key events captured with the `rdev` crate into engine keys.
+//!
+//! rdev reports keys by their position on a qwerty layout:
+//!
+//! - `KeyA` to `KeyZ` and `Num0` to `Num9` map to the letter and digit keys,
+//!   `Kp0` to `Kp9` to the keypad digits
+//! - `Space`, `Tab`, `Return` and `KpReturn` map to the whitespace keys
+//! - `Backspace` and the four arrows map to their keys
+//! - `Minus`, `Quote`, `BackQuote` and `BackSlash` map to `-`, `'`, `` ` ``
+//!   and `\`
+//!
+//! Every other key, mouse events and the modifiers themselves give `None`.
+use crate::engine::{keycodes, KeyCap, KeyState, PhysicKey};
+use ::rdev::{Event, EventType, Key};
+
+const KEYS: [(Key, u32); 59] = [
+    (Key::KeyA, keycodes::KEY_A),
+    (Key::KeyB, keycodes::KEY_B),
+    (Key::KeyC, keycodes::KEY_C),
+    (Key::KeyD, keycodes::KEY_D),
+    (Key::KeyE, keycodes::KEY_E),
+    (Key::KeyF, keycodes::KEY_F),
+    (Key::KeyG, keycodes::KEY_G),
+    (Key::KeyH, keycodes::KEY_H),
+    (Key::KeyI, keycodes::KEY_I),
+    (Key::KeyJ, keycodes::KEY_J),
+    (Key::KeyK, keycodes::KEY_K),
+    (Key::KeyL, keycodes::KEY_L),
+    (Key::KeyM, keycodes::KEY_M),
+    (Key::KeyN, keycodes::KEY_N),
+    (Key::KeyO, keycodes::KEY_O),
+    (Key::KeyP, keycodes::KEY_P),
+    (Key::KeyQ, keycodes::KEY_Q),
+    (Key::KeyR, keycodes::KEY_R),
+    (Key::KeyS, keycodes::KEY_S),
+    (Key::KeyT, keycodes::KEY_T),
+    (Key::KeyU, keycodes::KEY_U),
+    (Key::KeyV, keycodes::KEY_V),
+    (Key::KeyW, keycodes::KEY_W),
+    (Key::KeyX, keycodes::KEY_X),
+    (Key::KeyY, keycodes::KEY_Y),
+    (Key::KeyZ, keycodes::KEY_Z),
+    (Key::Num1, keycodes::KEY_1),
+    (Key::Num2, keycodes::KEY_2),
+    (Key::Num3, keycodes::KEY_3),
+    (Key::Num4, keycodes::KEY_4),
+    (Key::Num5, keycodes::KEY_5),
+    (Key::Num6, keycodes::KEY_6),
+    (Key::Num7, keycodes::KEY_7),
+    (Key::Num8, keycodes::KEY_8),
+    (Key::Num9, keycodes::KEY_9),
+    (Key::Num0, keycodes::KEY_0),
+    (Key::Kp1, keycodes::KEY_KP_1),
+    (Key::Kp2, keycodes::KEY_KP_2),
+    (Key::Kp3, keycodes::KEY_KP_3),
+    (Key::Kp4, keycodes::KEY_KP_4),
+    (Key::Kp5, keycodes::KEY_KP_5),
+    (Key::Kp6, keycodes::KEY_KP_6),
+    (Key::Kp7, keycodes::KEY_KP_7),
+    (Key::Kp8, keycodes::KEY_KP_8),
+    (Key::Kp9, keycodes::KEY_KP_9),
+    (Key::Kp0, keycodes::KEY_KP_0),
+    (Key::Minus, keycodes::KEY_MINUS),
+    (Key::Quote, keycodes::KEY_APOSTROPHE),
+    (Key::BackQuote, keycodes::KEY_GRAVE),
+    (Key::BackSlash, keycodes::KEY_BACKSLASH),
+    (Key::Space, keycodes::KEY_SPACE),
+    (Key::Tab, keycodes::KEY_TAB),
+    (Key::Return, keycodes::KEY_ENTER),
+    (Key::Backspace, keycodes::KEY_BACKSPACE),
+    (Key::LeftArrow, keycodes::KEY_LEFT),
+    (Key::RightArrow, keycodes::KEY_RIGHT),
+    (Key::UpArrow, keycodes::KEY_UP),
+    (Key::DownArrow, keycodes::KEY_DOWN),
+    (Key::KpReturn, keycodes::KEY_ENTER),
+];
+
+/// The key `event` is pressing or releasing, without any modifier since a
+/// single event doesn't tell whether shift is held, use `RdevKeys` for that
+pub fn rdev_to_physic_key(event: &Event) -> Option<PhysicKey> {
+    let (key, state) = match event.event_type {
+        EventType::KeyPress(key) => (key, KeyState::KeyPress),
+        EventType::KeyRelease(key) => (key, KeyState::KeyRelease),
+        _ => return None,
+    };
+    KEYS.iter()
+        .find(|&&(rdev_key, _)| rdev_key == key)
+        .map(|&(_, keycode)| PhysicKey { keycode, state, cap: None })
+}
+
+/// Converts a stream of rdev events, keeping track of shift and caps lock
+/// so the keys carry their modifier
+///
+/// Shift wins when both are on, feed it every event including the
+/// releases or shift gets stuck.
+#[derive(Debug, Default)]
+pub struct RdevKeys {
+    shift_held: bool,
+    caps_lock: bool,
+}
+
+impl RdevKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn to_physic_key(&mut self, event: &Event) -> Option<PhysicKey> {
+        match event.event_type {
+            EventType::KeyPress(Key::ShiftLeft) | EventType::KeyPress(Key::ShiftRight) => {
+                self.shift_held = true;
+            }
+            EventType::KeyRelease(Key::ShiftLeft) | EventType::KeyRelease(Key::ShiftRight) => {
+                self.shift_held = false;
+            }
+            EventType::KeyPress(Key::CapsLock) => {
+                self.caps_lock = !self.caps_lock;
+            }
+            _ => {}
+        }
+        let mut key = rdev_to_physic_key(event)?;
+        key.cap = if self.shift_held {
+            Some(KeyCap::Shift)
+        } else if self.caps_lock {
+            Some(KeyCap::CapsLock)
+        } else {
+            None
+        };
+        Some(key)
+    }
+}
@@ -0,0 +1,11 @@
+pub mod engine;
+pub mod keyboard;
+
+#[cfg(feature = "legacy-encodings")]
+pub mod encoding;
+
+#[cfg(feature = "escape")]
+pub mod escape;
+
+#[cfg(feature = "combining")]
+pub mod combining;
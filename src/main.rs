@@ -1,9 +1,5 @@
-mod engine;
-mod keyboard;
-
-use keyboard::get_keyboard;
-
-use engine::{Engine, Action};
+use vi_rs::keyboard::get_keyboard;
+use vi_rs::engine::{Engine, Action};
 
 fn main() {
     let mut keyboard = get_keyboard();
@@ -18,6 +14,10 @@ fn main() {
             match instruction {
                 Action::Insert(ch) => keyboard.insert(ch),
                 Action::Backspace(amount) => keyboard.backspace(amount),
+                Action::ReplaceWord(amount, word) => {
+                    keyboard.backspace(amount);
+                    word.chars().for_each(|ch| keyboard.insert(ch));
+                }
             }
         }
     }